@@ -0,0 +1,229 @@
+#ifndef LIP_SYNC_H
+#define LIP_SYNC_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Outcome of a call, negative values being errors.
+ */
+typedef enum LipSyncStatus {
+  LIP_SYNC_STATUS_OK = 0,
+  /**
+   * No result is ready yet
+   */
+  LIP_SYNC_STATUS_EMPTY = 1,
+  LIP_SYNC_STATUS_WORKER_DIED = -1,
+  LIP_SYNC_STATUS_INVALID_INPUT = -2,
+  LIP_SYNC_STATUS_PROFILE = -3,
+  LIP_SYNC_STATUS_CONFIGURATION = -4,
+  /**
+   * Analysis panicked on a frame, the stream keeps running
+   */
+  LIP_SYNC_STATUS_PANICKED = -5,
+  LIP_SYNC_STATUS_NULL_POINTER = -6,
+} LipSyncStatus;
+
+/**
+ * Opaque stream created by `lip_sync_create`.
+ */
+typedef struct LipSyncHandle LipSyncHandle;
+
+/**
+ * Analysis settings, see `lip_sync_default_config` for the defaults.
+ */
+typedef struct LipSyncConfig {
+  /**
+   * Samples used per analysis, a power of 2 from 64 to 8192
+   */
+  uint32_t fft_samples;
+  /**
+   * Loudness range in dB mapped to an amount of 0 to 1
+   */
+  float dynamic_range;
+  /**
+   * Minimum height of a spectral envelope peak, relative to the highest point
+   */
+  float peak_threshold;
+  /**
+   * Number of cepstral coefficients kept when smoothing the spectrum
+   */
+  int32_t lifter_level;
+  /**
+   * Values at or below this are cut from the spectrum, at most `fft_samples / 2`
+   */
+  int32_t filter_low;
+  /**
+   * Values at or above this are cut from the spectrum, at most `fft_samples / 2`
+   */
+  int32_t filter_high;
+  /**
+   * 0 L1, 1 L2, 2 cosine
+   */
+  int32_t compare_method;
+  /**
+   * Seconds for the output to follow a rising amount, 0 to follow immediately
+   */
+  float attack_time;
+  /**
+   * Seconds for the output to follow a falling amount, 0 to follow immediately
+   */
+  float release_time;
+} LipSyncConfig;
+
+/**
+ * Analysis of one frame. Per-phoneme weights are read with `lip_sync_last_weights`.
+ */
+typedef struct LipSyncResult {
+  /**
+   * Closest phoneme before smoothing, -1 if none
+   */
+  int32_t estimate;
+  /**
+   * Phoneme after smoothing, -1 if none
+   */
+  int32_t vowel;
+  float amount;
+  /**
+   * 0 closed to 1 fully open
+   */
+  float mouth_open;
+  /**
+   * -1 pouted to 1 smiling
+   */
+  float mouth_form;
+  /**
+   * First and second formant positions as FFT bins, 0 if no peaks were found
+   */
+  float f1;
+  float f2;
+  /**
+   * Time span of the analyzed frame, in seconds
+   */
+  double start_time;
+  double end_time;
+  /**
+   * Seconds between the frame being pushed and the result being ready
+   */
+  double latency;
+} LipSyncResult;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Applies new settings between frames. The previous settings are kept if they are invalid.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be destroyed. `config` must
+ * be null or readable.
+ */
+LipSyncStatus lip_sync_configure(LipSyncHandle *handle, const LipSyncConfig *config);
+
+/**
+ * Creates a stream analyzing audio at `sample_rate` with the default settings, on a shared
+ * worker pool when `threaded` or otherwise as samples are pushed. Returns null on failure.
+ */
+LipSyncHandle *lip_sync_create(bool threaded, double sample_rate);
+
+/**
+ * Fills `config` with the default settings.
+ *
+ * # Safety
+ *
+ * `config` must be null or writable.
+ */
+LipSyncStatus lip_sync_default_config(LipSyncConfig *config);
+
+/**
+ * Shuts the stream down and frees the handle. Null is ignored.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be used after this call.
+ */
+void lip_sync_destroy(LipSyncHandle *handle);
+
+/**
+ * Message of the last error returned through the handle, empty if there was none.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be destroyed.
+ */
+const char *lip_sync_last_error(const LipSyncHandle *handle);
+
+/**
+ * Copies up to `len` per-phoneme weights of the last pulled result into `weights`. Returns how
+ * many were copied.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be destroyed. `weights` must
+ * be null or have room for `len` values.
+ */
+size_t lip_sync_last_weights(const LipSyncHandle *handle, float *weights, size_t len);
+
+/**
+ * Replaces the phoneme templates with a nul-terminated JSON profile, in the layout written by
+ * the Godot node's `get_profile_json`.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be destroyed. `json` must be
+ * null or a nul-terminated string.
+ */
+LipSyncStatus lip_sync_load_profile(LipSyncHandle *handle, const char *json);
+
+/**
+ * Number of phonemes in the profile, which is also the length of the weights.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be destroyed.
+ */
+size_t lip_sync_phoneme_count(const LipSyncHandle *handle);
+
+/**
+ * Takes the oldest result into `result`, returning `Empty` when none is ready. Frames that
+ * failed are reported in order with their error status, see `lip_sync_last_error`.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be destroyed. `result` must
+ * be null or writable.
+ */
+LipSyncStatus lip_sync_pull_result(LipSyncHandle *handle, LipSyncResult *result);
+
+/**
+ * Queues `len` mono samples starting at `start_time` seconds, or right after the previously
+ * pushed samples when negative. At least `fft_samples` samples are needed per call.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `lip_sync_create` and not be destroyed. `samples` must
+ * be null or hold `len` readable values.
+ */
+LipSyncStatus lip_sync_push_samples(LipSyncHandle *handle,
+                                    const float *samples,
+                                    size_t len,
+                                    double start_time);
+
+/**
+ * Stops the shared worker pool and waits for its threads to exit. Call it after destroying
+ * every handle and before unloading the library, since the workers run its code. Handles
+ * created afterwards start a new pool.
+ */
+void lip_sync_shutdown(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LIP_SYNC_H */
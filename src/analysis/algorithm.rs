@@ -1,6 +1,6 @@
 use std::boxed::Box;
 
-use crate::model::{DataPoint, INV_LOG10, PI2};
+use crate::analysis::model::{DataPoint, INV_LOG10, PI2};
 
 pub fn rms(data: &[f32]) -> f32 {
     let mut rms: f32 = 0.0;
@@ -42,7 +42,7 @@ pub fn hamming(data: &mut [f32]) {
     let n = data.len();
     for i in data.iter_mut() {
         let h = 0.54 - 0.46 * (*PI2 * *i / (n as f32 - 1.0));
-        *i = *i * h;
+        *i *= h;
     }
     data[0] = 0.0;
     data[n - 1] = 0.0;
@@ -81,11 +81,11 @@ pub fn fft(data: &mut [Box<DataPoint>], reverse: bool) {
 
     let mut b = vec![];
     let mut c = vec![];
-    for i in 0..n {
+    for (i, v) in data.iter().enumerate() {
         if i % 2 == 0 {
-            b.push(data[i].clone());
-        } else if i % 2 == 1 {
-            c.push(data[i].clone());
+            b.push(v.clone());
+        } else {
+            c.push(v.clone());
         }
     }
     fft(b.as_mut_slice(), reverse);
@@ -0,0 +1,678 @@
+use std::{collections::VecDeque, sync::mpsc, sync::Arc};
+
+use crate::analysis::{
+    debug::DebugFrame,
+    error::LipSyncError,
+    input::ChannelMode,
+    job::{Frame, Job, JobMessage, Supervisor, DEFAULT_MAX_RESTARTS},
+    model::{Profile, VowelEstimate},
+    pool::{self, OverflowPolicy, StreamHandle},
+    profile,
+    settings::LipSyncSettings,
+    stats::JobStats,
+    tap::{Tap, TapReader},
+};
+
+/// Where frames are analyzed.
+enum Backend {
+    /// On the shared worker pool
+    Threaded(StreamHandle),
+    /// On the calling thread, as soon as frames are pushed or polled from the tap
+    Inline {
+        /// Boxed since a job is much larger than a stream handle
+        job: Box<Job>,
+        supervisor: Supervisor,
+        tap: Option<TapReader>,
+    },
+    Stopped,
+}
+
+/// A lip sync stream fed with mono samples or a bus tap, analyzed either on the shared worker
+/// pool or on the calling thread. Settings, profile and tap are kept by the analyzer, so they
+/// carry over when it is restarted in the other mode.
+pub struct Analyzer {
+    backend: Backend,
+    settings: LipSyncSettings,
+    profile: Option<Profile>,
+    tap: Option<(Arc<Tap>, ChannelMode)>,
+    debug: bool,
+    max_restarts: u32,
+    /// Input queue of the pool stream, kept so it survives restarts
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    sample_rate: f64,
+    /// Position right after the last pushed frame, used to timestamp untimed frames
+    sample_position: u64,
+    /// Results of frames analyzed on the calling thread, waiting for `poll`. The oldest are
+    /// dropped past `DEFAULT_OUTPUT_CAPACITY`, like a threaded stream's queue.
+    results: VecDeque<Result<VowelEstimate, LipSyncError>>,
+    /// Results dropped from `results` since the analyzer was started
+    dropped_results: u64,
+    /// Frames rejected by `push` or `process` since the analyzer was started
+    rejected_frames: u64,
+}
+
+impl Analyzer {
+    /// Creates a stopped analyzer with the default settings, see `start`.
+    pub fn new(sample_rate: f64) -> Self {
+        Analyzer {
+            backend: Backend::Stopped,
+            settings: LipSyncSettings::default(),
+            profile: None,
+            tap: None,
+            debug: false,
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            queue_capacity: pool::DEFAULT_INPUT_CAPACITY,
+            overflow_policy: OverflowPolicy::DropOldest,
+            sample_rate,
+            sample_position: 0,
+            results: VecDeque::new(),
+            dropped_results: 0,
+            rejected_frames: 0,
+        }
+    }
+
+    /// Registers with the worker pool, or creates a job on the calling thread when not
+    /// `threaded`. Any previous stream is shut down and its pending results discarded.
+    pub fn start(&mut self, threaded: bool) -> Result<(), LipSyncError> {
+        self.stop().ok();
+        self.results.clear();
+        self.dropped_results = 0;
+        self.rejected_frames = 0;
+
+        let reader = self.tap_reader();
+        if threaded {
+            let stream = pool::register().ok_or_else(|| {
+                LipSyncError::WorkerDied("Unable to register with worker pool".to_owned())
+            })?;
+            stream.set_max_restarts(self.max_restarts);
+            stream.set_capacity(self.queue_capacity);
+            stream.set_policy(self.overflow_policy);
+
+            let mut messages = vec![
+                JobMessage::Configure(self.settings.clone()),
+                JobMessage::SetDebug(self.debug),
+                JobMessage::AttachTap(reader),
+            ];
+            if let Some(profile) = self.profile.clone() {
+                messages.push(JobMessage::SetProfile(profile));
+            }
+            for msg in messages {
+                stream.send(msg).map_err(|_| {
+                    LipSyncError::WorkerDied("Unable to configure worker pool".to_owned())
+                })?;
+            }
+            self.backend = Backend::Threaded(stream);
+        } else {
+            let mut job = Box::new(Job::new());
+            job.configure(self.settings.clone())?;
+            job.set_debug(self.debug);
+            if let Some(profile) = self.profile.clone() {
+                job.set_profile(profile);
+            }
+
+            let mut supervisor = Supervisor::new();
+            supervisor.max_restarts = self.max_restarts;
+            self.backend = Backend::Inline {
+                job,
+                supervisor,
+                tap: reader,
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Shuts the stream down. Fails if it was not running.
+    pub fn stop(&mut self) -> Result<(), LipSyncError> {
+        match std::mem::replace(&mut self.backend, Backend::Stopped) {
+            // Dropping the handle unregisters the stream from the pool
+            Backend::Threaded(stream) => {
+                stream.send(JobMessage::Shutdown).ok();
+                Ok(())
+            }
+            Backend::Inline { .. } => Ok(()),
+            Backend::Stopped => Err(LipSyncError::WorkerDied(
+                "Stream has already been shut down".to_owned(),
+            )),
+        }
+    }
+
+    pub fn is_threaded(&self) -> bool {
+        matches!(self.backend, Backend::Threaded(_))
+    }
+
+    /// Validates the settings and applies them between frames, keeping the old ones on failure.
+    pub fn configure(&mut self, settings: LipSyncSettings) -> Result<(), LipSyncError> {
+        settings.validate()?;
+
+        match &mut self.backend {
+            Backend::Threaded(stream) => {
+                if stream
+                    .send(JobMessage::Configure(settings.clone()))
+                    .is_err()
+                {
+                    return Err(LipSyncError::WorkerDied(
+                        "Unable to send settings to worker pool".to_owned(),
+                    ));
+                }
+            }
+            Backend::Inline { job, .. } => job.configure(settings.clone())?,
+            Backend::Stopped => {}
+        }
+        self.settings = settings;
+
+        Ok(())
+    }
+
+    /// Profile set with `set_profile`, `None` while the built-in one is used.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Replaces the phoneme templates, keeping the current ones if `profile` is incomplete.
+    pub fn set_profile(&mut self, profile: Profile) -> Result<(), LipSyncError> {
+        profile::validate(&profile)?;
+        self.send_or_apply(JobMessage::SetProfile(profile.clone()), "profile")?;
+        self.profile = Some(profile);
+
+        Ok(())
+    }
+
+    /// Starts or stops capturing the buffers of every frame for `debug_frame`.
+    pub fn set_debug(&mut self, enabled: bool) -> Result<(), LipSyncError> {
+        self.debug = enabled;
+        self.send_or_apply(JobMessage::SetDebug(enabled), "debug flag")
+    }
+
+    /// Sets how many panics in the analysis are recovered from before the stream shuts down.
+    pub fn set_max_restarts(&mut self, max_restarts: u32) {
+        self.max_restarts = max_restarts;
+        match &mut self.backend {
+            Backend::Threaded(stream) => stream.set_max_restarts(max_restarts),
+            Backend::Inline { supervisor, .. } => supervisor.max_restarts = max_restarts,
+            Backend::Stopped => {}
+        }
+    }
+
+    /// Reads frames from `tap`, or stops reading when `None`. In threaded mode frames are read
+    /// on the worker pool as soon as they are mixed, otherwise they are read by `poll`.
+    pub fn set_tap(
+        &mut self,
+        tap: Option<Arc<Tap>>,
+        channel_mode: ChannelMode,
+    ) -> Result<(), LipSyncError> {
+        self.tap = tap.map(|v| (v, channel_mode));
+        let reader = self.tap_reader();
+        self.send_or_apply(JobMessage::AttachTap(reader), "tap")
+    }
+
+    /// Samples lost because the tap was not read fast enough.
+    pub fn tap_overruns(&self) -> u64 {
+        self.tap.as_ref().map_or(0, |(v, _)| v.overruns())
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) -> Result<(), LipSyncError> {
+        if sample_rate.is_nan() || sample_rate <= 0.0 {
+            return Err(LipSyncError::Configuration(format!(
+                "Invalid sample rate {}",
+                sample_rate
+            )));
+        }
+        self.sample_rate = sample_rate;
+
+        Ok(())
+    }
+
+    /// Samples pushed so far, or the position right after the last timestamped frame.
+    pub fn sample_position(&self) -> u64 {
+        self.sample_position
+    }
+
+    pub fn set_sample_position(&mut self, sample_position: u64) {
+        self.sample_position = sample_position;
+    }
+
+    /// Queues mono samples starting at `start_time` seconds, or right after the previous frame
+    /// when `None`. Frames are analyzed right away when not threaded, their results waiting for
+    /// `poll` either way.
+    pub fn push(&mut self, samples: Vec<f32>, start_time: Option<f64>) -> Result<(), LipSyncError> {
+        let frame = self.make_frame(samples, start_time)?;
+        match &mut self.backend {
+            Backend::Threaded(stream) => stream.send(JobMessage::InputData(frame)).map_err(|_| {
+                LipSyncError::WorkerDied("Unable to send stream to worker pool".to_owned())
+            }),
+            Backend::Inline { .. } => {
+                let result = self.run_inline(&frame);
+                self.queue_result(result);
+                Ok(())
+            }
+            Backend::Stopped => Err(LipSyncError::WorkerDied(
+                "Stream has been shut down".to_owned(),
+            )),
+        }
+    }
+
+    /// Analyzes mono samples right away and returns the result, only available when not
+    /// threaded.
+    pub fn process(
+        &mut self,
+        samples: Vec<f32>,
+        start_time: Option<f64>,
+    ) -> Result<VowelEstimate, LipSyncError> {
+        if self.is_threaded() {
+            return Err(LipSyncError::Configuration(
+                "Frames can only be processed right away when not threaded".to_owned(),
+            ));
+        }
+        let frame = self.make_frame(samples, start_time)?;
+        self.run_inline(&frame)
+    }
+
+    /// Takes every result that is ready, in order. When not threaded, frames waiting in the
+    /// tap are analyzed first.
+    pub fn poll(&mut self) -> Vec<Result<VowelEstimate, LipSyncError>> {
+        match &mut self.backend {
+            Backend::Threaded(_) => self.drain_stream(),
+            Backend::Inline { tap, .. } => {
+                if let Some(mut reader) = tap.take() {
+                    reader.frame_size = self.settings.fft_samples;
+                    while let Some(frame) = reader.next_frame() {
+                        let result = self.run_inline(&frame);
+                        self.queue_result(result);
+                        if matches!(self.backend, Backend::Stopped) {
+                            break;
+                        }
+                    }
+                    if let Backend::Inline { tap, .. } = &mut self.backend {
+                        *tap = Some(reader);
+                    }
+                }
+                self.results.drain(..).collect()
+            }
+            Backend::Stopped => self.results.drain(..).collect(),
+        }
+    }
+
+    /// Frame counts and per-stage timings of the analysis. Frames rejected before reaching the
+    /// job or dropped from a full queue count as skipped.
+    pub fn stats(&self) -> JobStats {
+        let mut stats = match &self.backend {
+            Backend::Threaded(stream) => stream.stats(),
+            Backend::Inline { job, .. } => job.stats.clone(),
+            Backend::Stopped => JobStats::default(),
+        };
+        stats.frames_skipped += self.rejected_frames + self.dropped_inputs();
+        stats
+    }
+
+    /// Buffers of the latest analyzed frame, `None` unless enabled with `set_debug`.
+    pub fn debug_frame(&self) -> Option<DebugFrame> {
+        match &self.backend {
+            Backend::Threaded(stream) => stream.debug_frame(),
+            Backend::Inline { job, .. } => job.debug_frame().cloned(),
+            Backend::Stopped => None,
+        }
+    }
+
+    /// Identifies the stream in the shared worker pool, `None` when not threaded.
+    pub fn stream_id(&self) -> Option<u64> {
+        self.stream().map(|v| v.id())
+    }
+
+    /// Number of input frames waiting to be analyzed.
+    pub fn queue_depth(&self) -> usize {
+        self.stream().map_or(0, |v| v.queue_depth())
+    }
+
+    /// Input frames dropped because the worker fell behind.
+    pub fn dropped_inputs(&self) -> u64 {
+        self.stream().map_or(0, |v| v.dropped_inputs())
+    }
+
+    /// Results dropped because they were not polled often enough.
+    pub fn dropped_outputs(&self) -> u64 {
+        self.stream()
+            .map_or(self.dropped_results, |v| v.dropped_outputs())
+    }
+
+    /// Sets how many input frames can be queued before the overflow policy applies. Only
+    /// threaded streams queue frames, but the value is kept for when one is started.
+    pub fn set_queue_capacity(&mut self, capacity: usize) {
+        self.queue_capacity = capacity.max(1);
+        if let Some(stream) = self.stream() {
+            stream.set_capacity(self.queue_capacity);
+        }
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    /// Sets what happens to frames pushed while the queue is full, kept like the capacity.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+        if let Some(stream) = self.stream() {
+            stream.set_policy(policy);
+        }
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    /// Keeps a result of the calling thread for `poll`, dropping the oldest once full.
+    fn queue_result(&mut self, result: Result<VowelEstimate, LipSyncError>) {
+        if self.results.len() >= pool::DEFAULT_OUTPUT_CAPACITY {
+            self.results.pop_front();
+            self.dropped_results += 1;
+        }
+        self.results.push_back(result);
+    }
+
+    fn stream(&self) -> Option<&StreamHandle> {
+        match &self.backend {
+            Backend::Threaded(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn tap_reader(&self) -> Option<TapReader> {
+        self.tap.as_ref().map(|(tap, channel_mode)| {
+            let mut reader = TapReader::new(tap.clone(), *channel_mode, self.sample_rate);
+            reader.frame_size = self.settings.fft_samples;
+            reader
+        })
+    }
+
+    /// Sends a message to the worker, or applies it to the inline job.
+    fn send_or_apply(&mut self, msg: JobMessage, what: &str) -> Result<(), LipSyncError> {
+        match &mut self.backend {
+            Backend::Threaded(stream) => stream.send(msg).map_err(|_| {
+                LipSyncError::WorkerDied(format!("Unable to send {} to worker pool", what))
+            }),
+            Backend::Inline { job, tap, .. } => {
+                match msg {
+                    JobMessage::SetProfile(profile) => job.set_profile(profile),
+                    JobMessage::SetDebug(enabled) => job.set_debug(enabled),
+                    JobMessage::AttachTap(reader) => *tap = reader,
+                    _ => {}
+                }
+                Ok(())
+            }
+            Backend::Stopped => Ok(()),
+        }
+    }
+
+    fn make_frame(
+        &mut self,
+        samples: Vec<f32>,
+        start_time: Option<f64>,
+    ) -> Result<Frame, LipSyncError> {
+        if samples.len() < self.settings.fft_samples {
+            self.rejected_frames += 1;
+            return Err(LipSyncError::InvalidInput(format!(
+                "Expected at least {} samples, got {}",
+                self.settings.fft_samples,
+                samples.len()
+            )));
+        }
+
+        let start_time = start_time.unwrap_or(self.sample_position as f64 / self.sample_rate);
+        let frame = Frame::new(samples, start_time, self.sample_rate);
+        self.sample_position = (frame.end_time * self.sample_rate).round() as u64;
+
+        Ok(frame)
+    }
+
+    /// Runs a frame through the inline job, stopping once it can no longer recover.
+    fn run_inline(&mut self, frame: &Frame) -> Result<VowelEstimate, LipSyncError> {
+        let result = match &mut self.backend {
+            Backend::Inline {
+                job, supervisor, ..
+            } => supervisor.run(job, frame),
+            _ => {
+                return Err(LipSyncError::WorkerDied(
+                    "Job has been shut down".to_owned(),
+                ))
+            }
+        };
+        if let Err(LipSyncError::WorkerDied(_)) = result {
+            self.backend = Backend::Stopped;
+        }
+
+        result
+    }
+
+    /// Takes every message waiting on the worker, stopping if it is gone. A worker that died is
+    /// reported once, whether it said so or just disconnected.
+    fn drain_stream(&mut self) -> Vec<Result<VowelEstimate, LipSyncError>> {
+        let mut out = vec![];
+        while let Some(stream) = self.stream() {
+            match stream.receiver.try_recv() {
+                Ok(JobMessage::OutputData(v)) => out.push(Ok(v)),
+                // The worker disconnects right after giving up, which is the same failure
+                Ok(JobMessage::Error(e @ LipSyncError::WorkerDied(_))) => {
+                    self.backend = Backend::Stopped;
+                    out.push(Err(e));
+                }
+                Ok(JobMessage::Error(e)) => out.push(Err(e)),
+                Ok(_) => {
+                    out.push(Err(LipSyncError::WorkerDied(
+                        "Received unexpected message from worker".to_owned(),
+                    )));
+                    self.stop().ok();
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(e) => {
+                    self.backend = Backend::Stopped;
+                    out.push(Err(LipSyncError::WorkerDied(e.to_string())));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    /// A vowel-like mix of a few formant frequencies.
+    fn voice(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                [(700.0, 0.5), (1200.0, 0.3), (2600.0, 0.2)]
+                    .iter()
+                    .map(|(f, a)| a * (2.0 * std::f32::consts::PI * f * t).sin())
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn inline() -> Analyzer {
+        let mut analyzer = Analyzer::new(SAMPLE_RATE);
+        analyzer.start(false).unwrap();
+        analyzer
+    }
+
+    #[test]
+    fn push_then_poll_inline() {
+        let mut analyzer = inline();
+        assert!(!analyzer.is_threaded());
+
+        analyzer.push(voice(1024), None).unwrap();
+        analyzer.push(voice(1024), None).unwrap();
+        let results = analyzer.poll();
+
+        assert_eq!(results.len(), 2);
+        let second = results[1].as_ref().unwrap();
+        assert!((second.start_time - 1024.0 / SAMPLE_RATE).abs() < 1e-9);
+        assert!((second.end_time - 2048.0 / SAMPLE_RATE).abs() < 1e-9);
+        assert_eq!(analyzer.sample_position(), 2048);
+        assert!(analyzer.poll().is_empty());
+    }
+
+    #[test]
+    fn push_uses_given_start_time() {
+        let mut analyzer = inline();
+
+        analyzer.push(voice(1024), Some(2.0)).unwrap();
+        let results = analyzer.poll();
+
+        assert_eq!(results[0].as_ref().unwrap().start_time, 2.0);
+    }
+
+    #[test]
+    fn push_rejects_short_frames() {
+        let mut analyzer = inline();
+
+        let result = analyzer.push(voice(100), None);
+
+        assert!(matches!(result, Err(LipSyncError::InvalidInput(_))));
+        assert_eq!(analyzer.sample_position(), 0);
+        assert_eq!(analyzer.stats().frames_skipped, 1);
+        assert_eq!(analyzer.stats().frames_processed, 0);
+    }
+
+    #[test]
+    fn push_fails_once_stopped() {
+        let mut analyzer = inline();
+        analyzer.stop().unwrap();
+
+        assert!(matches!(
+            analyzer.push(voice(1024), None),
+            Err(LipSyncError::WorkerDied(_))
+        ));
+        assert!(analyzer.stop().is_err());
+    }
+
+    #[test]
+    fn inline_results_drop_oldest() {
+        let mut analyzer = inline();
+
+        for _ in 0..pool::DEFAULT_OUTPUT_CAPACITY + 3 {
+            analyzer.push(voice(1024), None).unwrap();
+        }
+        let results = analyzer.poll();
+
+        assert_eq!(results.len(), pool::DEFAULT_OUTPUT_CAPACITY);
+        assert_eq!(analyzer.dropped_outputs(), 3);
+        let first = results[0].as_ref().unwrap();
+        assert!((first.start_time - 3.0 * 1024.0 / SAMPLE_RATE).abs() < 1e-9);
+
+        analyzer.start(false).unwrap();
+        assert_eq!(analyzer.dropped_outputs(), 0);
+    }
+
+    #[test]
+    fn process_returns_result_right_away() {
+        let mut analyzer = inline();
+
+        let result = analyzer.process(voice(1024), None).unwrap();
+
+        assert_eq!(result.weights.len(), Profile::default().phonemes.len());
+        assert!(analyzer.poll().is_empty());
+    }
+
+    #[test]
+    fn reconfigure_changes_frame_size() {
+        let mut analyzer = inline();
+        let settings = LipSyncSettings {
+            fft_samples: 512,
+            ..LipSyncSettings::default()
+        };
+
+        analyzer.configure(settings).unwrap();
+
+        analyzer.push(voice(512), None).unwrap();
+        assert_eq!(analyzer.poll().len(), 1);
+    }
+
+    #[test]
+    fn invalid_settings_keep_previous_ones() {
+        let mut analyzer = inline();
+        let settings = LipSyncSettings {
+            fft_samples: 1000,
+            ..LipSyncSettings::default()
+        };
+
+        let result = analyzer.configure(settings);
+
+        assert!(matches!(result, Err(LipSyncError::Configuration(_))));
+        assert!(analyzer.push(voice(512), None).is_err());
+        assert!(analyzer.push(voice(1024), None).is_ok());
+    }
+
+    #[test]
+    fn settings_carry_over_restart() {
+        let mut analyzer = Analyzer::new(SAMPLE_RATE);
+        let settings = LipSyncSettings {
+            fft_samples: 256,
+            ..LipSyncSettings::default()
+        };
+        analyzer.configure(settings).unwrap();
+
+        analyzer.start(false).unwrap();
+
+        analyzer.push(voice(256), None).unwrap();
+        assert!(analyzer.poll()[0].is_ok());
+    }
+
+    #[test]
+    fn profile_applies_to_results() {
+        let mut analyzer = inline();
+        assert!(analyzer.profile().is_none());
+
+        let mut profile = Profile::default();
+        profile.phonemes.truncate(3);
+        profile.peaks3.truncate(3);
+        profile.peaks4.truncate(3);
+        profile.mouth_shapes.truncate(3);
+        analyzer.set_profile(profile).unwrap();
+
+        assert_eq!(analyzer.profile().map(|v| v.phonemes.len()), Some(3));
+        analyzer.push(voice(1024), None).unwrap();
+        let results = analyzer.poll();
+        assert_eq!(results[0].as_ref().unwrap().weights.len(), 3);
+    }
+
+    #[test]
+    fn queue_settings_carry_over_restart() {
+        let mut analyzer = inline();
+
+        analyzer.set_queue_capacity(0);
+        analyzer.set_overflow_policy(OverflowPolicy::CoalesceLatest);
+        analyzer.start(false).unwrap();
+
+        assert_eq!(analyzer.queue_capacity(), 1);
+        assert_eq!(analyzer.overflow_policy(), OverflowPolicy::CoalesceLatest);
+    }
+
+    #[test]
+    fn rejects_incomplete_profiles() {
+        let mut analyzer = inline();
+        let mut profile = Profile::default();
+        profile.peaks4.pop();
+
+        let result = analyzer.set_profile(profile);
+
+        assert!(matches!(result, Err(LipSyncError::Profile(_))));
+        assert!(analyzer.profile().is_none());
+        analyzer.push(voice(1024), None).unwrap();
+        assert!(analyzer.poll()[0].is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_sample_rate() {
+        let mut analyzer = inline();
+
+        assert!(analyzer.set_sample_rate(0.0).is_err());
+        assert!(analyzer.set_sample_rate(f64::NAN).is_err());
+        assert!(analyzer.set_sample_rate(48000.0).is_ok());
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::Path;
+
+use crate::analysis::{
+    error::LipSyncError,
+    input::{downmix, ChannelMode},
+    job::{Frame, Job, Supervisor},
+    log,
+    model::{MouthShape, Profile},
+    profile,
+    settings::LipSyncSettings,
+    timeline::{Keyframe, Timeline},
+    transcript::{self, Transcript},
+    wav::{self, Wav},
+};
+
+/// Keyframes along with the distances they were estimated from, see `Job::distances`.
+struct Analysis {
+    timeline: Timeline,
+    distances: Vec<Vec<f32>>,
+    profile: Profile,
+}
+
+/// Runs mono samples through the analysis every `hop` seconds with `profile`'s templates and
+/// collects the results.
+pub fn bake(
+    samples: &[f32],
+    sample_rate: f64,
+    hop: f64,
+    settings: LipSyncSettings,
+    profile: Profile,
+) -> Result<Timeline, LipSyncError> {
+    Ok(analyze(samples, sample_rate, hop, settings, profile)?.timeline)
+}
+
+/// Bakes samples of a line whose transcript is known, aligning the vowels its rules expect
+/// against the analysis and pulling keyframes towards them by its strength.
+pub fn bake_transcript(
+    samples: &[f32],
+    sample_rate: f64,
+    hop: f64,
+    settings: LipSyncSettings,
+    profile: Profile,
+    line: &Transcript,
+) -> Result<Timeline, LipSyncError> {
+    let mut analysis = analyze(samples, sample_rate, hop, settings, profile)?;
+    let phonemes = analysis.profile.phonemes.as_slice();
+    let expected = transcript::expected_phonemes(line.text, line.rules, phonemes);
+    transcript::constrain(
+        &mut analysis.timeline,
+        analysis.distances.as_slice(),
+        expected.as_slice(),
+        &analysis.profile,
+        line.strength,
+    );
+
+    Ok(analysis.timeline)
+}
+
+fn analyze(
+    samples: &[f32],
+    sample_rate: f64,
+    hop: f64,
+    settings: LipSyncSettings,
+    profile: Profile,
+) -> Result<Analysis, LipSyncError> {
+    if sample_rate <= 0.0 || hop <= 0.0 {
+        return Err(LipSyncError::Configuration(format!(
+            "Invalid sample rate {} or hop {}",
+            sample_rate, hop
+        )));
+    }
+
+    let fft_samples = settings.fft_samples;
+    let hop_samples = ((hop * sample_rate).round() as usize).max(1);
+
+    profile::validate(&profile)?;
+    let mut job = Job::new();
+    job.set_profile(profile);
+    job.configure(settings)?;
+    let mut supervisor = Supervisor::new();
+    let mut timeline = Timeline::new(job.phonemes().to_vec());
+    let mut distances = vec![];
+
+    let mut panicked = 0;
+    let mut start = 0;
+    while start + fft_samples <= samples.len() {
+        let frame = Frame::new(
+            samples[start..start + fft_samples].to_vec(),
+            start as f64 / sample_rate,
+            sample_rate,
+        );
+        match supervisor.run(&mut job, &frame) {
+            Ok(v) => {
+                timeline.keyframes.push(Keyframe::from(&v));
+                distances.push(job.distances().to_vec());
+            }
+            // A single bad frame should not ruin the whole bake, so it holds the previous
+            // keyframe, or rests at the start
+            Err(LipSyncError::Panicked { .. }) => {
+                let mut keyframe = match timeline.keyframes.last() {
+                    Some(v) => v.clone(),
+                    None => Keyframe {
+                        time: 0.0,
+                        vowel: -1,
+                        amount: 0.0,
+                        weights: vec![0.0; timeline.phonemes.len()],
+                        mouth: MouthShape::zero(),
+                    },
+                };
+                keyframe.time = frame.start_time;
+                timeline.keyframes.push(keyframe);
+                distances.push(vec![]);
+                panicked += 1;
+            }
+            Err(e) => return Err(e),
+        }
+        start += hop_samples;
+    }
+    if panicked > 0 {
+        log::print(&format!(
+            "{} of {} baked frames could not be analyzed and hold the previous keyframe",
+            panicked,
+            timeline.keyframes.len()
+        ));
+    }
+
+    Ok(Analysis {
+        timeline,
+        distances,
+        profile: job.profile().clone(),
+    })
+}
+
+/// Bakes a decoded WAV file, averaging all channels.
+pub fn bake_wav(
+    wav: &Wav,
+    hop: f64,
+    settings: LipSyncSettings,
+    profile: Profile,
+) -> Result<Timeline, LipSyncError> {
+    let samples = downmix(wav.samples.as_slice(), wav.channels, ChannelMode::Mid);
+    bake(
+        samples.as_slice(),
+        wav.sample_rate as f64,
+        hop,
+        settings,
+        profile,
+    )
+}
+
+/// Bakes a decoded WAV file against its transcript, averaging all channels.
+pub fn bake_wav_transcript(
+    wav: &Wav,
+    hop: f64,
+    settings: LipSyncSettings,
+    profile: Profile,
+    line: &Transcript,
+) -> Result<Timeline, LipSyncError> {
+    let samples = downmix(wav.samples.as_slice(), wav.channels, ChannelMode::Mid);
+    let sample_rate = wav.sample_rate as f64;
+    bake_transcript(
+        samples.as_slice(),
+        sample_rate,
+        hop,
+        settings,
+        profile,
+        line,
+    )
+}
+
+/// Reads and bakes a WAV file from disk.
+pub fn bake_file(
+    path: &Path,
+    hop: f64,
+    settings: LipSyncSettings,
+    profile: Profile,
+) -> Result<Timeline, LipSyncError> {
+    bake_wav(&wav::read(path)?, hop, settings, profile)
+}
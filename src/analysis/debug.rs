@@ -0,0 +1,59 @@
+use crate::analysis::{log, model::DataPoint};
+
+pub fn print_max(sample_vec: Vec<f32>) {
+    let mut max: f32 = 0.0;
+    for i in sample_vec {
+        max = max.max(i.abs());
+    }
+    log::print(&max.to_string());
+}
+
+pub fn print_min(sample_vec: Vec<f32>) {
+    let mut min: f32 = 0.0;
+    for i in sample_vec {
+        min = min.min(i.abs());
+    }
+    log::print(&min.to_string());
+}
+
+/// Intermediate results of the last frame a job analyzed. Only captured once enabled with
+/// `Job::set_debug`, since copying the buffers costs time on every frame.
+#[derive(Debug, Clone)]
+pub struct DebugFrame {
+    /// Samples of the frame after the Hamming window
+    pub windowed: Vec<f32>,
+    /// Magnitude of each FFT bin up to Nyquist, smoothed with the previous frame
+    pub magnitude: Vec<f32>,
+    /// Band-limited log magnitude spectrum, normalized
+    pub log_spectrum: Vec<f32>,
+    /// Liftered spectral envelope the peaks are picked from
+    pub envelope: Vec<f32>,
+    /// Peaks found in the envelope, as bin and amplitude relative to the first peak
+    pub peaks: Vec<DataPoint>,
+    /// Peaks averaged over the last frames, which are compared against the profile
+    pub averaged_peaks: Vec<DataPoint>,
+    /// Distance to each phoneme of the profile, empty if no peaks were compared
+    pub distances: Vec<f32>,
+    /// Closest phoneme of this frame alone, -1 without 3 or 4 peaks
+    pub estimate: i32,
+    /// Phoneme after smoothing against the previous frames
+    pub vowel: i32,
+    pub amount: f32,
+}
+
+impl Default for DebugFrame {
+    fn default() -> Self {
+        DebugFrame {
+            windowed: vec![],
+            magnitude: vec![],
+            log_spectrum: vec![],
+            envelope: vec![],
+            peaks: vec![],
+            averaged_peaks: vec![],
+            distances: vec![],
+            estimate: -1,
+            vowel: -1,
+            amount: 0.0,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+
+/// Everything that can go wrong when running lip sync.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LipSyncError {
+    /// The worker processing the stream is gone, either because it was shut down or it panicked
+    WorkerDied(String),
+    /// Audio data that cannot be analyzed
+    InvalidInput(String),
+    /// A profile that could not be loaded or does not match the analysis
+    Profile(String),
+    /// Settings that are out of range or inconsistent
+    Configuration(String),
+    /// Analysis panicked on a frame. The job was reset and the stream kept running
+    Panicked {
+        message: String,
+        /// Index of the offending frame in the stream
+        frame: u64,
+        /// Number of samples in the offending frame
+        samples: usize,
+        /// Restarts so far, including this one
+        restarts: u32,
+    },
+}
+
+impl LipSyncError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LipSyncError::WorkerDied(_) => "worker_died",
+            LipSyncError::InvalidInput(_) => "invalid_input",
+            LipSyncError::Profile(_) => "profile",
+            LipSyncError::Configuration(_) => "configuration",
+            LipSyncError::Panicked { .. } => "panicked",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            LipSyncError::WorkerDied(v)
+            | LipSyncError::InvalidInput(v)
+            | LipSyncError::Profile(v)
+            | LipSyncError::Configuration(v) => v.as_str(),
+            LipSyncError::Panicked { message, .. } => message.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for LipSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for LipSyncError {}
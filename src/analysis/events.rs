@@ -0,0 +1,323 @@
+use crate::analysis::model::VowelEstimate;
+
+pub const DEFAULT_SPEECH_THRESHOLD: f32 = 0.2;
+pub const DEFAULT_SPEECH_START_DELAY: f64 = 0.05;
+pub const DEFAULT_SPEECH_END_DELAY: f64 = 0.25;
+pub const DEFAULT_PHONEME_HOLD: f64 = 0.06;
+pub const DEFAULT_LEVEL_PEAK_THRESHOLD: f32 = 0.8;
+
+/// Debouncing of the events derived from results. Delays are in seconds of audio.
+#[derive(Debug, Clone)]
+pub struct EventSettings {
+    /// Amount above which a frame counts as speech
+    pub speech_threshold: f32,
+    /// How long the amount must stay above the threshold before speech starts
+    pub speech_start_delay: f64,
+    /// How long the amount must stay below the threshold before speech ends
+    pub speech_end_delay: f64,
+    /// How long a new phoneme must be held before it replaces the current one
+    pub phoneme_hold: f64,
+    /// Amount a peak must reach to be reported
+    pub level_peak_threshold: f32,
+}
+
+impl Default for EventSettings {
+    fn default() -> Self {
+        EventSettings {
+            speech_threshold: DEFAULT_SPEECH_THRESHOLD,
+            speech_start_delay: DEFAULT_SPEECH_START_DELAY,
+            speech_end_delay: DEFAULT_SPEECH_END_DELAY,
+            phoneme_hold: DEFAULT_PHONEME_HOLD,
+            level_peak_threshold: DEFAULT_LEVEL_PEAK_THRESHOLD,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LipSyncEvent {
+    /// Phoneme indices, -1 when speech ends
+    PhonemeChanged {
+        old: i32,
+        new: i32,
+    },
+    SpeechStarted,
+    /// Seconds from the start of speech to the first frame below the threshold
+    SpeechEnded {
+        duration: f64,
+    },
+    /// Highest amount reached while above the peak threshold
+    LevelPeaked {
+        amount: f32,
+    },
+}
+
+/// Turns a stream of results into debounced speech, phoneme and level events.
+#[derive(Debug, Default)]
+pub struct EventDetector {
+    last_time: Option<f64>,
+    /// Start of speech, `None` while silent
+    speech_start: Option<f64>,
+    above_since: Option<f64>,
+    below_since: Option<f64>,
+    phoneme: i32,
+    /// Phoneme waiting to be held long enough, and since when
+    candidate: Option<(i32, f64)>,
+    /// Highest amount of the current excursion above the peak threshold
+    level_peak: Option<f32>,
+    /// Whether the current excursion has been reported
+    peak_reported: bool,
+}
+
+impl EventDetector {
+    pub fn new() -> Self {
+        EventDetector {
+            phoneme: -1,
+            ..Default::default()
+        }
+    }
+
+    /// Forgets the current speech and phoneme without emitting anything, used after seeking.
+    pub fn reset(&mut self) {
+        *self = EventDetector::new();
+    }
+
+    /// Feeds the next result and returns the events it caused, in order.
+    pub fn push(&mut self, v: &VowelEstimate, settings: &EventSettings) -> Vec<LipSyncEvent> {
+        let time = v.start_time;
+        if self.last_time.is_some_and(|last| time < last) {
+            self.reset();
+        }
+        self.last_time = Some(time);
+
+        let mut out = vec![];
+        self.update_speech(v.amount, time, settings, &mut out);
+        if self.speech_start.is_some() {
+            self.update_phoneme(v.vowel, time, settings, &mut out);
+        }
+        self.update_level(v.amount, settings, &mut out);
+
+        out
+    }
+
+    fn update_speech(
+        &mut self,
+        amount: f32,
+        time: f64,
+        settings: &EventSettings,
+        out: &mut Vec<LipSyncEvent>,
+    ) {
+        if amount >= settings.speech_threshold {
+            self.below_since = None;
+            if self.speech_start.is_none() {
+                let since = *self.above_since.get_or_insert(time);
+                if time - since >= settings.speech_start_delay {
+                    self.speech_start = Some(since);
+                    self.above_since = None;
+                    out.push(LipSyncEvent::SpeechStarted);
+                }
+            }
+            return;
+        }
+
+        self.above_since = None;
+        if let Some(start) = self.speech_start {
+            let since = *self.below_since.get_or_insert(time);
+            if time - since >= settings.speech_end_delay {
+                self.speech_start = None;
+                self.below_since = None;
+                self.candidate = None;
+                if self.phoneme != -1 {
+                    out.push(LipSyncEvent::PhonemeChanged {
+                        old: self.phoneme,
+                        new: -1,
+                    });
+                    self.phoneme = -1;
+                }
+                out.push(LipSyncEvent::SpeechEnded {
+                    duration: since - start,
+                });
+            }
+        }
+    }
+
+    fn update_phoneme(
+        &mut self,
+        vowel: i32,
+        time: f64,
+        settings: &EventSettings,
+        out: &mut Vec<LipSyncEvent>,
+    ) {
+        if vowel < 0 || vowel == self.phoneme {
+            self.candidate = None;
+            return;
+        }
+
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == vowel => since,
+            _ => {
+                self.candidate = Some((vowel, time));
+                time
+            }
+        };
+        if time - since >= settings.phoneme_hold {
+            out.push(LipSyncEvent::PhonemeChanged {
+                old: self.phoneme,
+                new: vowel,
+            });
+            self.phoneme = vowel;
+            self.candidate = None;
+        }
+    }
+
+    /// Reports the highest amount once per excursion above the threshold, as soon as it starts
+    /// to fall.
+    fn update_level(&mut self, amount: f32, settings: &EventSettings, out: &mut Vec<LipSyncEvent>) {
+        if amount < settings.level_peak_threshold {
+            // The excursion ended on its highest frame
+            if let Some(peak) = self.level_peak.take() {
+                out.push(LipSyncEvent::LevelPeaked { amount: peak });
+            }
+            self.peak_reported = false;
+            return;
+        }
+        if self.peak_reported {
+            return;
+        }
+
+        match self.level_peak {
+            Some(peak) if amount < peak => {
+                out.push(LipSyncEvent::LevelPeaked { amount: peak });
+                self.level_peak = None;
+                self.peak_reported = true;
+            }
+            _ => self.level_peak = Some(amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> EventSettings {
+        EventSettings {
+            speech_threshold: 0.2,
+            speech_start_delay: 0.15,
+            speech_end_delay: 0.25,
+            phoneme_hold: 0.15,
+            level_peak_threshold: 0.8,
+        }
+    }
+
+    /// Feeds `(vowel, amount)` frames 0.1 seconds apart and returns each event with the index
+    /// of the frame that caused it.
+    fn run(settings: &EventSettings, frames: &[(i32, f32)]) -> Vec<(usize, LipSyncEvent)> {
+        let mut detector = EventDetector::new();
+        let mut out = vec![];
+        for (i, &(vowel, amount)) in frames.iter().enumerate() {
+            let mut v = VowelEstimate::new(vowel, vowel, amount);
+            v.start_time = i as f64 * 0.1;
+            out.extend(detector.push(&v, settings).into_iter().map(|e| (i, e)));
+        }
+        out
+    }
+
+    #[test]
+    fn speech_starts_after_delay() {
+        let events = run(&settings(), &[(-1, 0.5); 4]);
+
+        assert_eq!(events, vec![(2, LipSyncEvent::SpeechStarted)]);
+    }
+
+    #[test]
+    fn short_bursts_do_not_start_speech() {
+        let frames = [(-1, 0.5), (-1, 0.5), (-1, 0.0), (-1, 0.5), (-1, 0.0)];
+
+        let events = run(&settings(), &frames);
+
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn speech_ends_after_delay() {
+        let mut frames = vec![(-1, 0.5); 3];
+        // A dip shorter than the end delay keeps speech going
+        frames.extend([(-1, 0.0), (-1, 0.5)]);
+        frames.extend([(-1, 0.0); 4]);
+
+        let events = run(&settings(), &frames);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], (2, LipSyncEvent::SpeechStarted));
+        match events[1] {
+            (8, LipSyncEvent::SpeechEnded { duration }) => assert!((duration - 0.5).abs() < 1e-9),
+            ref e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn phonemes_change_once_held() {
+        let mut frames = vec![(1, 0.5); 5];
+        // A single frame of another phoneme is not held long enough
+        frames.push((2, 0.5));
+        frames.extend([(1, 0.5); 2]);
+        frames.extend([(-1, 0.0); 4]);
+
+        let events = run(&settings(), &frames);
+
+        assert_eq!(
+            events,
+            vec![
+                (2, LipSyncEvent::SpeechStarted),
+                (4, LipSyncEvent::PhonemeChanged { old: -1, new: 1 }),
+                (11, LipSyncEvent::PhonemeChanged { old: 1, new: -1 }),
+                (11, LipSyncEvent::SpeechEnded { duration: 0.8 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn level_peaks_are_reported_once_per_excursion() {
+        let settings = EventSettings {
+            speech_threshold: 2.0,
+            ..settings()
+        };
+        let frames = [
+            (-1, 0.85),
+            (-1, 0.95),
+            (-1, 0.9),
+            (-1, 0.99),
+            (-1, 0.5),
+            (-1, 0.9),
+            (-1, 0.5),
+        ];
+
+        let events = run(&settings, &frames);
+
+        assert_eq!(
+            events,
+            vec![
+                (2, LipSyncEvent::LevelPeaked { amount: 0.95 }),
+                (6, LipSyncEvent::LevelPeaked { amount: 0.9 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn going_back_in_time_resets() {
+        let mut detector = EventDetector::new();
+        let settings = settings();
+        for i in 0..4 {
+            let mut v = VowelEstimate::new(-1, -1, 0.5);
+            v.start_time = i as f64 * 0.1;
+            detector.push(&v, &settings);
+        }
+
+        let mut v = VowelEstimate::new(-1, -1, 0.0);
+        v.start_time = 0.0;
+        let events = detector.push(&v, &settings);
+
+        assert!(events.is_empty());
+        assert!(detector.speech_start.is_none());
+    }
+}
@@ -0,0 +1,229 @@
+pub mod papagayo;
+pub mod rhubarb;
+
+use crate::analysis::{
+    model::{MouthShape, Profile},
+    timeline::{Keyframe, Timeline},
+};
+
+/// Amounts below this are written as the rest shape.
+pub const DEFAULT_SILENCE_THRESHOLD: f32 = 0.1;
+
+/// A mouth shape held from `start` to `end` seconds. All formats are read and written through
+/// cues.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub shape: String,
+}
+
+/// Maps between a tool's mouth shapes and the timeline's phonemes.
+pub struct ShapeSet {
+    /// Shape used when the mouth is closed
+    pub rest: &'static str,
+    /// Shape written for each phoneme, unknown phonemes use `fallback`
+    pub from_phoneme: &'static [(&'static str, &'static str)],
+    pub fallback: &'static str,
+    /// Phoneme and amount each shape is read back as, shapes missing here are read as rest
+    pub to_phoneme: &'static [(&'static str, &'static str, f32)],
+}
+
+impl ShapeSet {
+    fn shape_for(&self, keyframe: &Keyframe, phonemes: &[String], threshold: f32) -> &'static str {
+        if keyframe.vowel < 0 || keyframe.amount < threshold {
+            return self.rest;
+        }
+
+        let phoneme = match phonemes.get(keyframe.vowel as usize) {
+            Some(v) => v.as_str(),
+            None => return self.fallback,
+        };
+        self.from_phoneme
+            .iter()
+            .find(|(p, _)| *p == phoneme)
+            .map_or(self.fallback, |(_, s)| *s)
+    }
+}
+
+/// Collapses a timeline into cues, merging consecutive keyframes that show the same shape. The
+/// last keyframe is held for as long as the spacing before it, so it keeps its length.
+pub fn to_cues(timeline: &Timeline, shapes: &ShapeSet, threshold: f32) -> Vec<Cue> {
+    let keyframes = &timeline.keyframes;
+    let mut out: Vec<Cue> = vec![];
+
+    for (i, keyframe) in keyframes.iter().enumerate() {
+        let shape = shapes.shape_for(keyframe, timeline.phonemes.as_slice(), threshold);
+        let end = match (
+            keyframes.get(i + 1),
+            i.checked_sub(1).map(|v| &keyframes[v]),
+        ) {
+            (Some(next), _) => next.time,
+            (None, Some(prev)) => keyframe.time + (keyframe.time - prev.time),
+            (None, None) => keyframe.time,
+        };
+
+        match out.last_mut() {
+            Some(cue) if cue.shape == shape => cue.end = end,
+            _ => out.push(Cue {
+                start: keyframe.time,
+                end,
+                shape: shape.to_owned(),
+            }),
+        }
+    }
+
+    out
+}
+
+/// Expands cues into a timeline using the default profile's phonemes, with a keyframe at the
+/// start of every cue and a closing rest keyframe at the end.
+pub fn from_cues(cues: &[Cue], shapes: &ShapeSet) -> Timeline {
+    let profile = Profile::default();
+    let mut timeline = Timeline::new(profile.phonemes.clone());
+
+    for cue in cues {
+        let (vowel, amount) = shapes
+            .to_phoneme
+            .iter()
+            .find(|(s, _, _)| *s == cue.shape)
+            .and_then(|(_, p, a)| {
+                profile
+                    .phonemes
+                    .iter()
+                    .position(|v| v.as_str() == *p)
+                    .map(|i| (i as i32, *a))
+            })
+            .unwrap_or((-1, 0.0));
+
+        timeline
+            .keyframes
+            .push(keyframe(&profile, cue.start, vowel, amount));
+    }
+
+    if let Some(last) = cues.last() {
+        if last.end > last.start {
+            timeline
+                .keyframes
+                .push(keyframe(&profile, last.end, -1, 0.0));
+        }
+    }
+
+    timeline
+}
+
+fn keyframe(profile: &Profile, time: f64, vowel: i32, amount: f32) -> Keyframe {
+    let mut weights = vec![0.0; profile.phonemes.len()];
+    let mut mouth = MouthShape::zero();
+    if vowel >= 0 {
+        weights[vowel as usize] = 1.0;
+        mouth = profile.mouth_shapes[vowel as usize];
+        mouth.open *= amount;
+    }
+
+    Keyframe {
+        time,
+        vowel,
+        amount,
+        weights,
+        mouth,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline() -> Timeline {
+        let profile = Profile::default();
+        let mut timeline = Timeline::new(profile.phonemes.clone());
+        timeline.keyframes = vec![
+            keyframe(&profile, 0.0, 0, 1.0),
+            keyframe(&profile, 0.1, 0, 1.0),
+            keyframe(&profile, 0.2, 3, 0.8),
+            keyframe(&profile, 0.3, -1, 0.0),
+        ];
+        timeline
+    }
+
+    /// Times, vowels and amounts of the keyframes read back from any format.
+    fn assert_round_trip(read: Timeline) {
+        let expected = [(0.0, 0, 1.0), (0.2, 3, 0.8), (0.3, -1, 0.0), (0.4, -1, 0.0)];
+
+        assert_eq!(read.keyframes.len(), expected.len());
+        for (keyframe, (time, vowel, amount)) in read.keyframes.iter().zip(expected) {
+            assert!((keyframe.time - time).abs() < 1e-6, "{:?}", keyframe);
+            assert_eq!(keyframe.vowel, vowel);
+            assert_eq!(keyframe.amount, amount);
+        }
+    }
+
+    #[test]
+    fn merges_keyframes_into_cues() {
+        let cues = to_cues(&timeline(), &rhubarb::SHAPES, DEFAULT_SILENCE_THRESHOLD);
+
+        let shapes: Vec<&str> = cues.iter().map(|v| v.shape.as_str()).collect();
+        assert_eq!(shapes, ["D", "E", "X"]);
+        assert_eq!(cues[0].end, 0.2);
+        assert!((cues[2].end - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quiet_keyframes_are_rest() {
+        let profile = Profile::default();
+        let mut timeline = Timeline::new(profile.phonemes.clone());
+        timeline.keyframes = vec![keyframe(&profile, 0.0, 0, 0.05)];
+
+        let cues = to_cues(&timeline, &papagayo::SHAPES, DEFAULT_SILENCE_THRESHOLD);
+
+        let rest = Cue {
+            start: 0.0,
+            end: 0.0,
+            shape: "rest".to_owned(),
+        };
+        assert_eq!(cues, vec![rest]);
+    }
+
+    #[test]
+    fn rhubarb_round_trip() {
+        let timeline = timeline();
+
+        let threshold = DEFAULT_SILENCE_THRESHOLD;
+        assert_round_trip(rhubarb::read(&rhubarb::write_tsv(&timeline, threshold)).unwrap());
+        assert_round_trip(rhubarb::read(&rhubarb::write_json(&timeline, threshold, "")).unwrap());
+        assert_round_trip(rhubarb::read(&rhubarb::write_xml(&timeline, threshold, "")).unwrap());
+    }
+
+    #[test]
+    fn papagayo_round_trip() {
+        let text = papagayo::write(&timeline(), DEFAULT_SILENCE_THRESHOLD, 10.0);
+
+        assert_eq!(text, "MohoSwitch1\n1 AI\n3 O\n4 rest\n");
+        assert_round_trip(papagayo::read(&text, 10.0).unwrap());
+    }
+
+    #[test]
+    fn unknown_shapes_are_read_as_rest() {
+        let cues = [Cue {
+            start: 0.0,
+            end: 0.5,
+            shape: "?".to_owned(),
+        }];
+
+        let timeline = from_cues(&cues, &rhubarb::SHAPES);
+
+        assert_eq!(timeline.keyframes[0].vowel, -1);
+        assert_eq!(timeline.keyframes[1].time, 0.5);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(rhubarb::read("0.00\n").is_err());
+        assert!(rhubarb::read("{\"mouthCues\": 1}").is_err());
+        assert!(rhubarb::read("<mouthCue start=\"0\">A").is_err());
+        assert!(rhubarb::read("<mouthCue start=\"0\" end=\"1\" </mouthCue>").is_err());
+        assert!(papagayo::read("1 AI\n", 24.0).is_err());
+        assert!(papagayo::read("MohoSwitch1\nAI\n", 24.0).is_err());
+        assert!(papagayo::read("MohoSwitch1\n1 AI\n", 0.0).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use super::{from_cues, to_cues, Cue, ShapeSet};
+use crate::analysis::{error::LipSyncError, timeline::Timeline};
+
+const HEADER: &str = "MohoSwitch1";
+
+/// Preston Blair mouth shapes as used by Papagayo and Moho.
+pub const SHAPES: ShapeSet = ShapeSet {
+    rest: "rest",
+    from_phoneme: &[("A", "AI"), ("E", "E"), ("I", "E"), ("O", "O"), ("U", "U")],
+    fallback: "etc",
+    to_phoneme: &[
+        ("AI", "A", 1.0),
+        ("E", "E", 0.8),
+        ("O", "O", 0.8),
+        ("U", "U", 0.5),
+        ("WQ", "U", 0.4),
+        ("L", "E", 0.5),
+        ("FV", "I", 0.3),
+        ("etc", "I", 0.5),
+    ],
+};
+
+/// Writes a Moho switch file, with frames numbered from 1 at `fps`.
+pub fn write(timeline: &Timeline, threshold: f32, fps: f64) -> String {
+    let mut out = format!("{}\n", HEADER);
+    for cue in to_cues(timeline, &SHAPES, threshold) {
+        out.push_str(&format!(
+            "{} {}\n",
+            time_to_frame(cue.start, fps),
+            cue.shape
+        ));
+    }
+
+    out
+}
+
+/// Reads a Moho switch file. Each shape lasts until the next one, the last one for one frame.
+pub fn read(text: &str, fps: f64) -> Result<Timeline, LipSyncError> {
+    if fps <= 0.0 {
+        return Err(LipSyncError::Configuration(format!("Invalid fps {}", fps)));
+    }
+
+    let mut lines = text.lines().filter(|v| !v.trim().is_empty());
+    match lines.next() {
+        Some(v) if v.trim() == HEADER => {}
+        _ => return Err(invalid("Missing MohoSwitch1 header")),
+    }
+
+    let mut starts: Vec<(f64, String)> = vec![];
+    for line in lines {
+        let mut parts = line.split_whitespace();
+        let frame = parts
+            .next()
+            .and_then(|v| v.parse::<i64>().ok())
+            .ok_or_else(|| invalid(&format!("Invalid line '{}'", line)))?;
+        let shape = parts
+            .next()
+            .ok_or_else(|| invalid(&format!("Missing shape in line '{}'", line)))?;
+        starts.push(((frame - 1).max(0) as f64 / fps, shape.to_owned()));
+    }
+
+    let cues: Vec<Cue> = starts
+        .iter()
+        .enumerate()
+        .map(|(i, (start, shape))| Cue {
+            start: *start,
+            end: starts.get(i + 1).map_or(start + 1.0 / fps, |v| v.0),
+            shape: shape.clone(),
+        })
+        .collect();
+
+    Ok(from_cues(cues.as_slice(), &SHAPES))
+}
+
+fn time_to_frame(time: f64, fps: f64) -> i64 {
+    (time * fps).round() as i64 + 1
+}
+
+fn invalid(message: &str) -> LipSyncError {
+    LipSyncError::InvalidInput(format!("Invalid Papagayo file: {}", message))
+}
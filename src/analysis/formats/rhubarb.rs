@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+use super::{from_cues, to_cues, Cue, ShapeSet};
+use crate::analysis::{error::LipSyncError, timeline::Timeline};
+
+/// Rhubarb Lip Sync's mouth shapes. A is closed (M, B, P), B slightly open (most consonants and
+/// EE), C open (EH), D wide open (AA), E rounded (AO), F puckered (UW, W), G upper teeth on lower
+/// lip (F, V), H tongue raised (L) and X idle.
+pub const SHAPES: ShapeSet = ShapeSet {
+    rest: "X",
+    from_phoneme: &[("A", "D"), ("E", "C"), ("I", "B"), ("O", "E"), ("U", "F")],
+    fallback: "B",
+    to_phoneme: &[
+        ("B", "I", 0.5),
+        ("C", "E", 0.8),
+        ("D", "A", 1.0),
+        ("E", "O", 0.8),
+        ("F", "U", 0.5),
+        ("G", "I", 0.3),
+        ("H", "E", 0.5),
+    ],
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    #[serde(default)]
+    metadata: Metadata,
+    mouth_cues: Vec<MouthCue>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    #[serde(default)]
+    sound_file: String,
+    #[serde(default)]
+    duration: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MouthCue {
+    start: f64,
+    end: f64,
+    value: String,
+}
+
+/// Writes tab separated `start shape` lines, ending with the time the last shape ends.
+pub fn write_tsv(timeline: &Timeline, threshold: f32) -> String {
+    let cues = to_cues(timeline, &SHAPES, threshold);
+
+    let mut out = String::new();
+    for cue in cues.iter() {
+        out.push_str(&format!("{:.2}\t{}\n", cue.start, cue.shape));
+    }
+    if let Some(last) = cues.last() {
+        out.push_str(&format!("{:.2}\t{}\n", last.end, SHAPES.rest));
+    }
+
+    out
+}
+
+pub fn write_json(timeline: &Timeline, threshold: f32, sound_file: &str) -> String {
+    let document = Document {
+        metadata: Metadata {
+            sound_file: sound_file.to_owned(),
+            duration: timeline.length(),
+        },
+        mouth_cues: to_cues(timeline, &SHAPES, threshold)
+            .into_iter()
+            .map(|v| MouthCue {
+                start: v.start,
+                end: v.end,
+                value: v.shape,
+            })
+            .collect(),
+    };
+
+    serde_json::to_string_pretty(&document).unwrap_or_default()
+}
+
+pub fn write_xml(timeline: &Timeline, threshold: f32, sound_file: &str) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rhubarbResult>\n");
+    out.push_str("  <metadata>\n");
+    out.push_str(&format!(
+        "    <soundFile>{}</soundFile>\n",
+        escape_xml(sound_file)
+    ));
+    out.push_str(&format!(
+        "    <duration>{:.2}</duration>\n",
+        timeline.length()
+    ));
+    out.push_str("  </metadata>\n");
+    out.push_str("  <mouthCues>\n");
+    for cue in to_cues(timeline, &SHAPES, threshold) {
+        out.push_str(&format!(
+            "    <mouthCue start=\"{:.2}\" end=\"{:.2}\">{}</mouthCue>\n",
+            cue.start, cue.end, cue.shape
+        ));
+    }
+    out.push_str("  </mouthCues>\n</rhubarbResult>\n");
+
+    out
+}
+
+/// Reads any of Rhubarb's TSV, JSON or XML outputs, detected from the content.
+pub fn read(text: &str) -> Result<Timeline, LipSyncError> {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with('{') {
+        read_json(text)
+    } else if trimmed.starts_with('<') {
+        read_xml(text)
+    } else {
+        read_tsv(text)
+    }
+}
+
+pub fn read_tsv(text: &str) -> Result<Timeline, LipSyncError> {
+    let mut starts: Vec<(f64, String)> = vec![];
+    for line in text.lines().filter(|v| !v.trim().is_empty()) {
+        let mut parts = line.split_whitespace();
+        let start = parts
+            .next()
+            .and_then(|v| v.parse::<f64>().ok())
+            .ok_or_else(|| invalid(&format!("Invalid line '{}'", line)))?;
+        let shape = parts
+            .next()
+            .ok_or_else(|| invalid(&format!("Missing shape in line '{}'", line)))?;
+        starts.push((start, shape.to_owned()));
+    }
+
+    // Each shape lasts until the next one starts, the last line only marks the end
+    let cues: Vec<Cue> = starts
+        .windows(2)
+        .map(|v| Cue {
+            start: v[0].0,
+            end: v[1].0,
+            shape: v[0].1.clone(),
+        })
+        .collect();
+
+    Ok(from_cues(cues.as_slice(), &SHAPES))
+}
+
+pub fn read_json(text: &str) -> Result<Timeline, LipSyncError> {
+    let document: Document = serde_json::from_str(text).map_err(|e| invalid(&e.to_string()))?;
+    let cues: Vec<Cue> = document
+        .mouth_cues
+        .into_iter()
+        .map(|v| Cue {
+            start: v.start,
+            end: v.end,
+            shape: v.value,
+        })
+        .collect();
+
+    Ok(from_cues(cues.as_slice(), &SHAPES))
+}
+
+/// Reads the `mouthCue` elements of Rhubarb's XML output, ignoring everything else.
+pub fn read_xml(text: &str) -> Result<Timeline, LipSyncError> {
+    let mut cues = vec![];
+
+    let mut rest = text;
+    while let Some(i) = rest.find("<mouthCue ") {
+        rest = &rest[i..];
+        let tag_end = rest
+            .find('>')
+            .ok_or_else(|| invalid("Unterminated mouthCue"))?;
+        let close = rest
+            .find("</mouthCue>")
+            .ok_or_else(|| invalid("Unterminated mouthCue"))?;
+        if close < tag_end {
+            return Err(invalid("Unterminated mouthCue tag"));
+        }
+        let tag = &rest[..tag_end];
+
+        cues.push(Cue {
+            start: attribute(tag, "start")?,
+            end: attribute(tag, "end")?,
+            shape: rest[tag_end + 1..close].trim().to_owned(),
+        });
+        rest = &rest[close..];
+    }
+
+    Ok(from_cues(cues.as_slice(), &SHAPES))
+}
+
+fn attribute(tag: &str, name: &str) -> Result<f64, LipSyncError> {
+    let key = format!("{}=\"", name);
+    let start = tag
+        .find(&key)
+        .map(|v| v + key.len())
+        .ok_or_else(|| invalid(&format!("Missing {} attribute", name)))?;
+    let end = tag[start..]
+        .find('"')
+        .ok_or_else(|| invalid(&format!("Unterminated {} attribute", name)))?;
+
+    tag[start..start + end]
+        .parse::<f64>()
+        .map_err(|_| invalid(&format!("Invalid {} attribute", name)))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn invalid(message: &str) -> LipSyncError {
+    LipSyncError::InvalidInput(format!("Invalid Rhubarb file: {}", message))
+}
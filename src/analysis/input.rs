@@ -0,0 +1,146 @@
+/// Which channel to analyze when given multi-channel audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelMode {
+    Left,
+    Right,
+    /// Average of all channels
+    Mid,
+    /// Whichever channel has the most energy in the buffer
+    MaxEnergy,
+}
+
+impl From<i32> for ChannelMode {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => ChannelMode::Right,
+            2 => ChannelMode::Mid,
+            3 => ChannelMode::MaxEnergy,
+            _ => ChannelMode::Left,
+        }
+    }
+}
+
+impl From<ChannelMode> for i32 {
+    fn from(v: ChannelMode) -> Self {
+        match v {
+            ChannelMode::Left => 0,
+            ChannelMode::Right => 1,
+            ChannelMode::Mid => 2,
+            ChannelMode::MaxEnergy => 3,
+        }
+    }
+}
+
+/// Converts interleaved samples into mono samples. Trailing samples that do not make up a full
+/// frame are ignored.
+pub fn downmix(samples: &[f32], channels: usize, mode: ChannelMode) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+
+    let frames = samples.chunks_exact(channels);
+    match mode {
+        ChannelMode::Left => frames.map(|f| f[0]).collect(),
+        ChannelMode::Right => frames.map(|f| f[1]).collect(),
+        ChannelMode::Mid => {
+            let inv_channels = 1.0 / channels as f32;
+            frames
+                .map(|f| f.iter().sum::<f32>() * inv_channels)
+                .collect()
+        }
+        ChannelMode::MaxEnergy => {
+            let channel = loudest_channel(samples, channels);
+            frames.map(|f| f[channel]).collect()
+        }
+    }
+}
+
+fn loudest_channel(samples: &[f32], channels: usize) -> usize {
+    let mut energy = vec![0.0; channels];
+    for frame in samples.chunks_exact(channels) {
+        for (e, s) in energy.iter_mut().zip(frame.iter()) {
+            *e += s * s;
+        }
+    }
+
+    energy.iter().enumerate().fold(
+        0,
+        |max_idx, (i, e)| if *e > energy[max_idx] { i } else { max_idx },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEREO: [f32; 6] = [0.1, 0.5, 0.2, -0.6, 0.3, 0.7];
+
+    #[test]
+    fn mono_is_kept_as_is() {
+        let samples = [0.1, 0.2, 0.3];
+
+        assert_eq!(downmix(&samples, 1, ChannelMode::Right), samples.to_vec());
+        assert_eq!(downmix(&samples, 0, ChannelMode::Mid), samples.to_vec());
+    }
+
+    #[test]
+    fn picks_left_and_right() {
+        assert_eq!(downmix(&STEREO, 2, ChannelMode::Left), vec![0.1, 0.2, 0.3]);
+        assert_eq!(
+            downmix(&STEREO, 2, ChannelMode::Right),
+            vec![0.5, -0.6, 0.7]
+        );
+    }
+
+    #[test]
+    fn mid_averages_all_channels() {
+        let samples = [0.3, 0.6, 0.0, -0.3, 0.3, 0.9];
+
+        let mono = downmix(&samples, 3, ChannelMode::Mid);
+
+        assert_eq!(mono.len(), 2);
+        assert!((mono[0] - 0.3).abs() < 1e-6);
+        assert!((mono[1] - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn max_energy_picks_loudest_channel() {
+        // The right channel is louder even though the left one has the single largest sample
+        let samples = [0.9, 0.6, 0.0, -0.6, 0.0, 0.6];
+
+        assert_eq!(loudest_channel(&samples, 2), 1);
+        assert_eq!(
+            downmix(&samples, 2, ChannelMode::MaxEnergy),
+            vec![0.6, -0.6, 0.6]
+        );
+    }
+
+    #[test]
+    fn loudest_channel_prefers_first_on_ties() {
+        assert_eq!(loudest_channel(&[0.5, -0.5, 0.5, -0.5], 2), 0);
+        assert_eq!(loudest_channel(&[], 2), 0);
+    }
+
+    #[test]
+    fn ignores_trailing_partial_frame() {
+        let samples = [0.1, 0.5, 0.2, -0.6, 0.3];
+
+        assert_eq!(downmix(&samples, 2, ChannelMode::Left), vec![0.1, 0.2]);
+        assert_eq!(downmix(&samples, 2, ChannelMode::Mid).len(), 2);
+        // The partial frame does not count towards the energy either
+        assert_eq!(loudest_channel(&[0.1, 0.2, 0.9], 2), 1);
+    }
+
+    #[test]
+    fn maps_channel_modes() {
+        for mode in [
+            ChannelMode::Left,
+            ChannelMode::Right,
+            ChannelMode::Mid,
+            ChannelMode::MaxEnergy,
+        ] {
+            assert_eq!(ChannelMode::from(i32::from(mode)), mode);
+        }
+        assert_eq!(ChannelMode::from(7), ChannelMode::Left);
+    }
+}
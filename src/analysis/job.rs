@@ -0,0 +1,736 @@
+use crate::analysis::{
+    algorithm::*,
+    debug::DebugFrame,
+    error::LipSyncError,
+    log,
+    model::*,
+    settings::{CompareMethod, LipSyncSettings},
+    stats::{self, JobStats, StageTimer},
+    tap::TapReader,
+};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    time::Instant,
+};
+
+/// Panics a job can recover from before it is shut down.
+pub const DEFAULT_MAX_RESTARTS: u32 = 3;
+
+pub struct Job {
+    before_sample_array: Vec<f32>,
+    // TODO pretty sure these are just ring buffers
+    peaks3_log: VecDeque<Vec<DataPoint>>,
+    peaks4_log: VecDeque<Vec<DataPoint>>,
+    vowel_log: VecDeque<i32>,
+    estimate_log: VecDeque<i32>,
+    profile: Profile,
+    settings: LipSyncSettings,
+    /// Averaged peaks from the last estimation, empty if the frame was not estimated
+    last_peaks: Vec<DataPoint>,
+    /// Distance to each phoneme from the last estimation, empty if the frame was not estimated
+    last_distances: Vec<f32>,
+    /// Output of the previous frame after smoothing
+    smoothed: Option<Smoothed>,
+    /// Buffers of the last frame, `None` unless debugging is enabled
+    debug: Option<DebugFrame>,
+    /// Times the stages of the frame being executed
+    timer: StageTimer,
+    pub stats: JobStats,
+}
+
+/// The parts of a result eased by the attack and release times.
+struct Smoothed {
+    time: f64,
+    amount: f32,
+    weights: Vec<f32>,
+    mouth: MouthShape,
+}
+
+impl Default for Job {
+    fn default() -> Self {
+        Job::new()
+    }
+}
+
+impl Job {
+    pub fn new() -> Self {
+        Job {
+            before_sample_array: vec![],
+            peaks3_log: VecDeque::new(),
+            peaks4_log: VecDeque::new(),
+            vowel_log: VecDeque::from(vec![-1, -1, -1]),
+            estimate_log: VecDeque::from(vec![-1, -1, -1]),
+            profile: Profile::default(),
+            settings: LipSyncSettings::default(),
+            last_peaks: vec![],
+            last_distances: vec![],
+            smoothed: None,
+            debug: None,
+            timer: StageTimer::start(),
+            stats: JobStats::default(),
+        }
+    }
+
+    /// Clears all history while keeping the profile, settings and stats, used to recover after a
+    /// panic.
+    pub fn reset(&mut self) {
+        let profile = std::mem::take(&mut self.profile);
+        let settings = std::mem::take(&mut self.settings);
+        let stats = std::mem::take(&mut self.stats);
+        let debug = self.debug.is_some();
+        *self = Job::new();
+        self.profile = profile;
+        self.settings = settings;
+        self.stats = stats;
+        self.set_debug(debug);
+    }
+
+    pub fn phonemes(&self) -> &[String] {
+        self.profile.phonemes.as_slice()
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Replaces the phoneme templates. History is cleared since it was estimated against the
+    /// old templates.
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+        self.reset();
+    }
+
+    /// Distances from the last frame's formants to each phoneme's template, empty if the frame
+    /// had no usable peaks.
+    pub fn distances(&self) -> &[f32] {
+        self.last_distances.as_slice()
+    }
+
+    /// Starts or stops capturing the buffers of every frame for `debug_frame`.
+    pub fn set_debug(&mut self, enabled: bool) {
+        self.debug = if enabled {
+            Some(self.debug.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    /// Buffers of the last analyzed frame, `None` unless enabled with `set_debug`.
+    pub fn debug_frame(&self) -> Option<&DebugFrame> {
+        self.debug.as_ref()
+    }
+
+    /// Samples analyzed per frame.
+    pub fn frame_size(&self) -> usize {
+        self.settings.fft_samples
+    }
+
+    /// Applies new settings. History is cleared if the frame size changed since it can no longer
+    /// be compared against new frames.
+    pub fn configure(&mut self, settings: LipSyncSettings) -> Result<(), LipSyncError> {
+        settings.validate()?;
+
+        let resized = settings.fft_samples != self.settings.fft_samples;
+        self.settings = settings;
+        if resized {
+            self.reset();
+        }
+
+        Ok(())
+    }
+
+    pub fn execute(&mut self, stream: &[f32]) -> Result<VowelEstimate, LipSyncError> {
+        let mut data = stream.to_vec();
+        let fft_samples = self.settings.fft_samples;
+        let dynamic_range = self.settings.dynamic_range;
+
+        // Start from scratch so a frame that fails halfway never shows the previous one's buffers
+        if let Some(debug) = self.debug.as_mut() {
+            *debug = DebugFrame::default();
+        }
+
+        if data.len() < fft_samples {
+            self.stats.frames_skipped += 1;
+            return Err(LipSyncError::InvalidInput(format!(
+                "Audio data size {} is smaller than {} samples, skipped!",
+                data.len(),
+                fft_samples
+            )));
+        }
+
+        self.timer = StageTimer::start();
+
+        let rms = rms(data.as_slice());
+
+        data = data[..fft_samples].to_vec();
+        hamming(data.as_mut_slice());
+        if let Some(debug) = self.debug.as_mut() {
+            debug.windowed = data.clone();
+        }
+        self.timer.lap(stats::STAGE_WINDOWING);
+
+        rfft(data.as_mut_slice(), false, true);
+        data = data[..((fft_samples as f32 * 0.5) as usize) + 1].to_vec();
+        if !self.before_sample_array.is_empty() {
+            smoothing(data.as_mut_slice(), self.before_sample_array.as_slice());
+        }
+        self.before_sample_array = data.clone();
+        if let Some(debug) = self.debug.as_mut() {
+            debug.magnitude = data.clone();
+        }
+        self.timer.lap(stats::STAGE_FFT);
+
+        filter(
+            data.as_mut_slice(),
+            self.settings.filter_low,
+            self.settings.filter_high,
+        );
+        for i in data.iter_mut() {
+            *i = i.powi(2).ln() * *INV_LOG10;
+        }
+        normalize(data.as_mut_slice());
+        if let Some(debug) = self.debug.as_mut() {
+            debug.log_spectrum = data.clone();
+        }
+        rfft(data.as_mut_slice(), true, false);
+        lifter(data.as_mut_slice(), self.settings.lifter_level);
+        rfft(data.as_mut_slice(), false, false);
+        data = data[..((fft_samples as f32 * 0.25) as usize) + 1].to_vec();
+        normalize(data.as_mut_slice());
+        for i in data.iter_mut() {
+            *i = i.powi(2);
+        }
+        normalize(data.as_mut_slice());
+        let nrm_rms = dynamic_range.min((rms + dynamic_range).max(0.0));
+        let inv_dynamic_range = 1.0 / dynamic_range;
+        for i in data.iter_mut() {
+            *i = *i * nrm_rms * inv_dynamic_range;
+        }
+        let amount = inverse_lerp(-dynamic_range, 0.0, rms).clamp(0.0, 1.0);
+        self.timer.lap(stats::STAGE_CEPSTRUM);
+
+        let mut current_vowel = self.get_vowel(data.as_slice(), amount);
+        current_vowel.weights = self.get_weights(current_vowel.vowel);
+        current_vowel.mouth = self.get_mouth_shape(current_vowel.weights.as_slice(), amount);
+        if self.last_peaks.len() > 1 {
+            current_vowel.f1 = self.last_peaks[0].0;
+            current_vowel.f2 = self.last_peaks[1].0;
+        }
+        if let Some(debug) = self.debug.as_mut() {
+            debug.envelope = data.clone();
+            debug.averaged_peaks = self.last_peaks.clone();
+            debug.distances = self.last_distances.clone();
+            debug.estimate = current_vowel.estimate;
+            debug.vowel = current_vowel.vowel;
+            debug.amount = amount;
+        }
+        self.push_estimate(current_vowel.estimate);
+        self.push_vowel(current_vowel.vowel);
+        self.timer.lap(stats::STAGE_CLASSIFICATION);
+
+        let timings = std::mem::replace(&mut self.timer, StageTimer::start()).finish();
+        self.stats.record_frame(timings);
+
+        Ok(current_vowel)
+    }
+
+    fn get_peaks(&self, data: &[f32], threshold: f32) -> Vec<DataPoint> {
+        let n = data.len() - 1;
+        let mut i = 1;
+        let mut out = vec![];
+        let mut div = 1.0;
+        while i < n {
+            if data[i] > threshold && data[i] > data[i - 1] && data[i] > data[i + 1] {
+                if !out.is_empty() {
+                    out.push(DataPoint(i as f32, data[i] * div));
+                } else {
+                    out.push(DataPoint(i as f32, 1.0));
+                    div = 1.0 / data[i];
+                }
+            }
+            i += 1;
+        }
+        out
+    }
+    fn get_peaks_average(&mut self, size: usize) -> Vec<DataPoint> {
+        let mut out = vec![];
+        let mut i = 1;
+        let mut j: usize;
+        let mut div = 1.0;
+        match size {
+            3 => {
+                out = self.peaks3_log[0].clone();
+                while i < self.peaks3_log.len() {
+                    j = 0;
+                    while j < out.len() {
+                        out[j].0 += self.peaks3_log[i][j].0;
+                        out[j].1 += self.peaks3_log[i][j].1;
+                        j += 1;
+                    }
+                    i += 1;
+                }
+                div = 1.0 / self.peaks3_log.len() as f32;
+            }
+            4 => {
+                out = self.peaks4_log[0].clone();
+                while i < self.peaks4_log.len() {
+                    j = 0;
+                    while j < out.len() {
+                        out[j].0 += self.peaks4_log[i][j].0;
+                        out[j].1 += self.peaks4_log[i][j].1;
+                        j += 1;
+                    }
+                    i += 1;
+                }
+                div = 1.0 / self.peaks4_log.len() as f32;
+            }
+            _ => {}
+        }
+
+        for k in out.iter_mut() {
+            *k *= div;
+        }
+
+        out
+    }
+
+    fn get_distance_from_db(&self, data: &[DataPoint]) -> Vec<f32> {
+        let mut out = vec![];
+
+        let peak_est: &Vec<Phoneme> = match data.len() {
+            3 => &self.profile.peaks3,
+            4 => &self.profile.peaks4,
+            _ => {
+                return out;
+            }
+        };
+
+        for template in peak_est.iter().take(self.profile.phonemes.len()) {
+            let dist = match self.settings.compare_method {
+                CompareMethod::L1 => {
+                    let mut dist = 0.0;
+                    for j in 0..data.len() {
+                        let est = &template[j];
+                        dist += (est.0 - data[j].0).abs() * *INV_255 + (est.1 - data[j].1);
+                    }
+                    dist
+                }
+                CompareMethod::L2 => {
+                    let mut dist = 0.0;
+                    for j in 0..data.len() {
+                        let est = &template[j];
+                        dist +=
+                            ((est.0 - data[j].0) * *INV_255).powi(2) + (est.1 - data[j].1).powi(2);
+                    }
+                    dist.sqrt()
+                }
+                CompareMethod::Cosine => cosine_distance(template, data),
+                CompareMethod::Unknown(_) => unreachable!("rejected by validate"),
+            };
+            out.push(dist);
+        }
+
+        out
+    }
+
+    /// Eases the result towards the previous one. The attack time applies while the amount
+    /// rises and the release time while it falls, scaled by the time between the two frames.
+    fn smooth(&mut self, ve: &mut VowelEstimate) {
+        if let Some(prev) = self.smoothed.as_ref() {
+            let dt = (ve.start_time - prev.time) as f32;
+            let tau = if ve.amount > prev.amount {
+                self.settings.attack_time
+            } else {
+                self.settings.release_time
+            };
+
+            // Frames going back in time come from a seek, there is nothing to ease from
+            if tau > 0.0 && dt > 0.0 {
+                let f = 1.0 - (-dt / tau).exp();
+                let lerp = |a: f32, b: f32| a + f * (b - a);
+
+                ve.amount = lerp(prev.amount, ve.amount);
+                if prev.weights.len() == ve.weights.len() {
+                    for (w, p) in ve.weights.iter_mut().zip(prev.weights.iter()) {
+                        *w = lerp(*p, *w);
+                    }
+                }
+                ve.mouth = MouthShape::new(
+                    lerp(prev.mouth.open, ve.mouth.open),
+                    lerp(prev.mouth.form, ve.mouth.form),
+                );
+            }
+        }
+
+        self.smoothed = Some(Smoothed {
+            time: ve.start_time,
+            amount: ve.amount,
+            weights: ve.weights.clone(),
+            mouth: ve.mouth,
+        });
+    }
+
+    fn push_peaks(&mut self, data: &[DataPoint]) {
+        match data.len() {
+            3 => {
+                if self.peaks3_log.len() < 3 {
+                    self.peaks3_log.push_back(data.to_owned());
+                } else {
+                    self.peaks3_log.push_front(data.to_owned());
+                    self.peaks3_log.pop_back();
+                }
+            }
+            4 => {
+                if self.peaks4_log.len() < 3 {
+                    self.peaks4_log.push_back(data.to_owned());
+                } else {
+                    self.peaks4_log.push_front(data.to_owned());
+                    self.peaks4_log.pop_back();
+                }
+            }
+            _ => log::print("push_peaks encountered invalid data"),
+        }
+    }
+
+    fn estimate_vowel(&mut self, data: &[f32]) -> i32 {
+        self.last_peaks.clear();
+        self.last_distances.clear();
+
+        let peaks = self.get_peaks(data, self.settings.peak_threshold);
+        if let Some(debug) = self.debug.as_mut() {
+            debug.peaks = peaks.clone();
+        }
+        if peaks.len() != 3 && peaks.len() != 4 {
+            self.timer.lap(stats::STAGE_PEAKS);
+            return -1;
+        }
+
+        self.push_peaks(peaks.as_slice());
+
+        let peaks_ave = self.get_peaks_average(peaks.len());
+        self.timer.lap(stats::STAGE_PEAKS);
+
+        let distance_vowel = self.get_distance_from_db(peaks_ave.as_slice());
+
+        let mut i = 1;
+        let mut min_distance = distance_vowel[0];
+        let mut min_idx = 0;
+        while i < self.profile.phonemes.len() {
+            let dist = distance_vowel[i];
+            if dist < min_distance {
+                min_distance = dist;
+                min_idx = i as i32;
+            }
+            i += 1;
+        }
+
+        self.last_peaks = peaks_ave;
+        self.last_distances = distance_vowel;
+
+        min_idx
+    }
+
+    /// Converts the last distances into weights that sum to 1, sharpened by how close the
+    /// F1/F2 formants are to each phoneme's template. Falls back to a one-hot weight for `vowel`
+    /// when the frame could not be estimated.
+    fn get_weights(&self, vowel: i32) -> Vec<f32> {
+        let n = self.profile.phonemes.len();
+        let mut out = vec![0.0; n];
+
+        if self.last_distances.len() != n || self.last_peaks.len() < 2 {
+            if vowel >= 0 && (vowel as usize) < n {
+                out[vowel as usize] = 1.0;
+            }
+            return out;
+        }
+
+        let templates = match self.last_peaks.len() {
+            3 => &self.profile.peaks3,
+            _ => &self.profile.peaks4,
+        };
+        let f1 = self.last_peaks[0].0;
+        let f2 = self.last_peaks[1].0;
+        let min_distance = self
+            .last_distances
+            .iter()
+            .fold(f32::INFINITY, |acc, d| acc.min(*d));
+
+        let mut total = 0.0;
+        for i in 0..n {
+            let distance_weight =
+                (-(self.last_distances[i] - min_distance) * WEIGHT_SHARPNESS).exp();
+            let formant_distance =
+                ((templates[i][0].0 - f1).powi(2) + (templates[i][1].0 - f2).powi(2)).sqrt();
+            let formant_weight = 1.0 / (1.0 + formant_distance);
+
+            out[i] = distance_weight * formant_weight;
+            total += out[i];
+        }
+
+        if total > 0.0 {
+            for i in out.iter_mut() {
+                *i /= total;
+            }
+        }
+
+        out
+    }
+
+    fn get_mouth_shape(&self, weights: &[f32], amount: f32) -> MouthShape {
+        self.profile.mouth_shape(weights, amount)
+    }
+
+    /// Smooths the frame's estimate against the previous vowels. Without usable history the
+    /// estimate is used as is, so frames that could not be estimated report -1.
+    fn get_vowel(&mut self, data: &[f32], amount: f32) -> VowelEstimate {
+        let current = self.estimate_vowel(data);
+
+        let f_vowel = self.vowel_log[0];
+
+        if self.vowel_log[0] != -1 && amount < 0.5 {
+            return VowelEstimate::new(current, f_vowel, amount);
+        }
+
+        if self.vowel_log.len() > 2 {
+            if current == self.estimate_log[0] {
+                if current != -1 {
+                    return VowelEstimate::new(current, current, amount);
+                }
+            } else {
+                if f_vowel != -1 {
+                    return VowelEstimate::new(current, f_vowel, amount);
+                }
+            }
+        }
+
+        VowelEstimate::new(current, current, amount)
+    }
+
+    fn push_vowel(&mut self, vowel: i32) {
+        if self.vowel_log.len() < 3 {
+            self.vowel_log.push_back(vowel);
+        } else {
+            self.vowel_log.push_front(vowel);
+            self.vowel_log.pop_back();
+        }
+    }
+
+    fn push_estimate(&mut self, vowel: i32) {
+        if self.estimate_log.len() < 3 {
+            self.estimate_log.push_back(vowel);
+        } else {
+            self.estimate_log.push_front(vowel);
+            self.estimate_log.pop_back();
+        }
+    }
+}
+
+/// Runs frames through a `Job`, resetting it when the analysis panics. This is the single entry
+/// point for analysis, whether the job lives on a worker or on the main thread.
+#[derive(Debug, Clone, Copy)]
+pub struct Supervisor {
+    /// Input frames handed to the job so far
+    pub frames: u64,
+    pub restarts: u32,
+    pub max_restarts: u32,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Supervisor {
+            frames: 0,
+            restarts: 0,
+            max_restarts: DEFAULT_MAX_RESTARTS,
+        }
+    }
+
+    /// Analyzes a frame and timestamps the result. Returns `LipSyncError::WorkerDied` once the
+    /// restart limit is exceeded, after which the job should not be used anymore.
+    pub fn run(&mut self, job: &mut Job, frame: &Frame) -> Result<VowelEstimate, LipSyncError> {
+        let index = self.frames;
+        self.frames += 1;
+        job.stats.record_wait(frame.received.elapsed());
+
+        match panic::catch_unwind(AssertUnwindSafe(|| job.execute(frame.samples.as_slice()))) {
+            Ok(Ok(mut v)) => {
+                v.start_time = frame.start_time;
+                v.end_time = frame.end_time;
+                job.smooth(&mut v);
+                v.latency = frame.received.elapsed().as_secs_f64();
+                Ok(v)
+            }
+            Ok(Err(e)) => Err(e),
+            Err(payload) => {
+                self.restarts += 1;
+                job.reset();
+
+                let message = panic_message(payload.as_ref());
+                if self.restarts > self.max_restarts {
+                    return Err(LipSyncError::WorkerDied(format!(
+                        "Restart limit of {} reached, last panic on frame {}: {}",
+                        self.max_restarts, index, message
+                    )));
+                }
+
+                Err(LipSyncError::Panicked {
+                    message,
+                    frame: index,
+                    samples: frame.samples.len(),
+                    restarts: self.restarts,
+                })
+            }
+        }
+    }
+}
+
+/// One minus the cosine similarity of the peaks, with positions scaled like the amplitudes.
+fn cosine_distance(est: &Phoneme, data: &[DataPoint]) -> f32 {
+    let mut dot = 0.0;
+    let mut norm_est = 0.0;
+    let mut norm_data = 0.0;
+    for (e, d) in est.0.iter().zip(data.iter()) {
+        let (e0, d0) = (e.0 * *INV_255, d.0 * *INV_255);
+        dot += e0 * d0 + e.1 * d.1;
+        norm_est += e0 * e0 + e.1 * e.1;
+        norm_data += d0 * d0 + d.1 * d.1;
+    }
+
+    let norm = (norm_est * norm_data).sqrt();
+    if norm > 0.0 {
+        1.0 - dot / norm
+    } else {
+        1.0
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(v) = payload.downcast_ref::<&str>() {
+        v.to_string()
+    } else if let Some(v) = payload.downcast_ref::<String>() {
+        v.clone()
+    } else {
+        "Unknown panic".to_owned()
+    }
+}
+
+/// Mono samples along with where they sit in the audio timeline.
+pub struct Frame {
+    pub samples: Vec<f32>,
+    /// Time of the first sample, in seconds
+    pub start_time: f64,
+    /// Time just after the last sample, in seconds
+    pub end_time: f64,
+    /// When the frame was handed to the job, used to measure processing latency
+    pub received: Instant,
+}
+
+impl Frame {
+    pub fn new(samples: Vec<f32>, start_time: f64, sample_rate: f64) -> Self {
+        let end_time = start_time + samples.len() as f64 / sample_rate;
+
+        Frame {
+            samples,
+            start_time,
+            end_time,
+            received: Instant::now(),
+        }
+    }
+}
+
+pub enum JobMessage {
+    InputData(Frame),
+    /// Replaces the job's settings, applied between frames
+    Configure(LipSyncSettings),
+    /// Replaces the job's phoneme templates
+    SetProfile(Profile),
+    /// Starts reading frames from a bus tap, or stops when `None`
+    AttachTap(Option<TapReader>),
+    /// Starts or stops capturing `DebugFrame`s
+    SetDebug(bool),
+    OutputData(VowelEstimate),
+    Error(LipSyncError),
+    Shutdown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    fn frame(index: usize) -> Frame {
+        let len = LipSyncSettings::default().fft_samples;
+        let samples = (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * 700.0 * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+        Frame::new(samples, (index * len) as f64 / SAMPLE_RATE, SAMPLE_RATE)
+    }
+
+    /// Leaves a spectrum history shorter than the next frame's, so smoothing indexes past it
+    /// and panics. `Job::reset` clears it again.
+    fn corrupt(job: &mut Job) {
+        job.before_sample_array = vec![0.0];
+    }
+
+    #[test]
+    fn restarts_after_panic() {
+        let mut job = Job::new();
+        let mut supervisor = Supervisor::new();
+        corrupt(&mut job);
+
+        let result = supervisor.run(&mut job, &frame(0));
+
+        match result {
+            Err(LipSyncError::Panicked {
+                frame,
+                samples,
+                restarts,
+                ..
+            }) => {
+                assert_eq!(frame, 0);
+                assert_eq!(samples, LipSyncSettings::default().fft_samples);
+                assert_eq!(restarts, 1);
+            }
+            v => panic!("expected a panic, got {:?}", v),
+        }
+
+        let next = frame(1);
+        let v = supervisor.run(&mut job, &next).unwrap();
+
+        assert_eq!(supervisor.frames, 2);
+        assert_eq!(supervisor.restarts, 1);
+        assert_eq!(v.start_time, next.start_time);
+    }
+
+    #[test]
+    fn stops_at_restart_limit() {
+        let mut job = Job::new();
+        let mut supervisor = Supervisor::new();
+        supervisor.max_restarts = 2;
+
+        let results: Vec<_> = (0..3)
+            .map(|i| {
+                corrupt(&mut job);
+                supervisor.run(&mut job, &frame(i))
+            })
+            .collect();
+
+        assert!(matches!(
+            results[0],
+            Err(LipSyncError::Panicked { restarts: 1, .. })
+        ));
+        assert!(matches!(
+            results[1],
+            Err(LipSyncError::Panicked { restarts: 2, .. })
+        ));
+        assert!(matches!(results[2], Err(LipSyncError::WorkerDied(_))));
+    }
+}
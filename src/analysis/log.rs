@@ -0,0 +1,20 @@
+use std::sync::RwLock;
+
+/// Receives messages the pipeline cannot report through a `Result`.
+pub type Logger = fn(&str);
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Routes messages to `logger` instead of stderr, for example the engine's console.
+pub fn set_logger(logger: Option<Logger>) {
+    if let Ok(mut v) = LOGGER.write() {
+        *v = logger;
+    }
+}
+
+pub fn print(message: &str) {
+    match LOGGER.read().ok().and_then(|v| *v) {
+        Some(logger) => logger(message),
+        None => eprintln!("{}", message),
+    }
+}
@@ -0,0 +1,26 @@
+//! The analysis pipeline, free of Godot types so it can be driven by any engine. Godot nodes
+//! and the C API in `ffi` are both built on `Analyzer`.
+
+pub mod algorithm;
+pub mod analyzer;
+pub mod bake;
+pub mod debug;
+pub mod error;
+pub mod events;
+pub mod formats;
+pub mod input;
+pub mod job;
+pub mod log;
+pub mod model;
+pub mod pcm;
+pub mod pool;
+pub mod profile;
+pub mod ring;
+pub mod settings;
+pub mod stats;
+pub mod tap;
+pub mod timeline;
+pub mod transcript;
+pub mod wav;
+
+pub use analyzer::Analyzer;
@@ -1,13 +1,14 @@
-use godot::prelude::*;
 use lazy_static::lazy_static;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::HashMap,
     ops::{Add, Div, Index, Mul, MulAssign},
 };
 
 pub const FFT_SAMPLES: usize = 1024;
 // pub const UPDATE_FRAME: usize = 5;
 pub const DYNAMIC_RANGE: f32 = 100.0;
+/// How quickly phoneme weights fall off as distances grow
+pub const WEIGHT_SHARPNESS: f32 = 10.0;
 
 pub const VOWELS: [&str; 5] = ["A", "E", "I", "O", "U"];
 
@@ -109,10 +110,17 @@ lazy_static! {
             ]),
         ),
     ]);
+    pub static ref DEFAULT_MOUTH_SHAPES: HashMap<String, MouthShape> = HashMap::from([
+        ("A".to_owned(), MouthShape::new(1.0, 0.0)),
+        ("E".to_owned(), MouthShape::new(0.5, 0.6)),
+        ("I".to_owned(), MouthShape::new(0.3, 1.0)),
+        ("O".to_owned(), MouthShape::new(0.7, -0.6)),
+        ("U".to_owned(), MouthShape::new(0.3, -1.0)),
+    ]);
     pub static ref PI2: f32 = 2.0 * std::f32::consts::PI;
     pub static ref INV_255: f32 = 1.0 / 255.0;
     pub static ref INV_32767: f32 = 1.0 / 32767.0;
-    pub static ref INV_LOG10: f32 = 1.0 / (10.0 as f32).ln();
+    pub static ref INV_LOG10: f32 = 1.0 / 10.0_f32.ln();
     pub static ref INV_DYNAMIC_RANGE: f32 = 1.0 / DYNAMIC_RANGE;
 }
 
@@ -166,8 +174,8 @@ impl Div for DataPoint {
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Phoneme(Vec<DataPoint>);
+#[derive(Debug, PartialEq, Clone)]
+pub struct Phoneme(pub Vec<DataPoint>);
 
 impl Index<usize> for Phoneme {
     type Output = DataPoint;
@@ -176,11 +184,86 @@ impl Index<usize> for Phoneme {
     }
 }
 
+/// Continuous mouth parameters for parametric rigs like Live2D or Inochi2D.
+///
+/// `open` goes from 0 (closed) to 1 (fully open), `form` goes from -1 (pouted) to 1 (smiling).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MouthShape {
+    pub open: f32,
+    pub form: f32,
+}
+
+impl MouthShape {
+    pub fn new(open: f32, form: f32) -> Self {
+        MouthShape { open, form }
+    }
+
+    pub fn zero() -> Self {
+        MouthShape::new(0.0, 0.0)
+    }
+}
+
+/// Phoneme templates and their matching mouth shapes. All lists are indexed the same way as
+/// `phonemes`.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub phonemes: Vec<String>,
+    pub peaks3: Vec<Phoneme>,
+    pub peaks4: Vec<Phoneme>,
+    pub mouth_shapes: Vec<MouthShape>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        let phonemes: Vec<String> = VOWELS.iter().map(|v| v.to_string()).collect();
+
+        Profile {
+            peaks3: phonemes
+                .iter()
+                .map(|p| DEFAULT_ESTIMATES["peak3"][p].clone())
+                .collect(),
+            peaks4: phonemes
+                .iter()
+                .map(|p| DEFAULT_ESTIMATES["peak4"][p].clone())
+                .collect(),
+            mouth_shapes: phonemes.iter().map(|p| DEFAULT_MOUTH_SHAPES[p]).collect(),
+            phonemes,
+        }
+    }
+}
+
+impl Profile {
+    /// Blends the phonemes' mouth shapes by `weights`, opening the result by `amount`.
+    pub fn mouth_shape(&self, weights: &[f32], amount: f32) -> MouthShape {
+        let mut shape = MouthShape::zero();
+        for (weight, target) in weights.iter().zip(self.mouth_shapes.iter()) {
+            shape.open += weight * target.open;
+            shape.form += weight * target.form;
+        }
+        shape.open = (shape.open * amount).clamp(0.0, 1.0);
+        shape.form = shape.form.clamp(-1.0, 1.0);
+
+        shape
+    }
+}
+
 #[derive(Debug)]
 pub struct VowelEstimate {
     pub estimate: i32,
     pub vowel: i32,
     pub amount: f32,
+    /// Per-phoneme weights that sum to 1. One-hot on `vowel` when the frame itself had no
+    /// usable peaks, and all 0 when `vowel` is -1.
+    pub weights: Vec<f32>,
+    pub mouth: MouthShape,
+    /// First and second formant positions as FFT bins, 0 if no peaks were found.
+    pub f1: f32,
+    pub f2: f32,
+    /// Time span of the analyzed frame, in seconds
+    pub start_time: f64,
+    pub end_time: f64,
+    /// Seconds between the frame being submitted and the result being ready
+    pub latency: f64,
 }
 
 impl VowelEstimate {
@@ -189,18 +272,13 @@ impl VowelEstimate {
             estimate,
             vowel,
             amount,
+            weights: vec![],
+            mouth: MouthShape::zero(),
+            f1: 0.0,
+            f2: 0.0,
+            start_time: 0.0,
+            end_time: 0.0,
+            latency: 0.0,
         }
     }
 }
-
-impl From<VowelEstimate> for Dictionary {
-    fn from(ve: VowelEstimate) -> Self {
-        let mut dict = Dictionary::new();
-
-        dict.insert("estimate", ve.estimate);
-        dict.insert("vowel", ve.vowel);
-        dict.insert("amount", ve.amount);
-
-        dict
-    }
-}
@@ -0,0 +1,166 @@
+use crate::analysis::error::LipSyncError;
+
+/// Sample encodings accepted for raw PCM data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    /// 8-bit unsigned, centered on 128
+    U8,
+    S16Le,
+    S16Be,
+    S24Le,
+    S24Be,
+    S32Le,
+    S32Be,
+    F32Le,
+    F32Be,
+}
+
+impl TryFrom<i32> for PcmFormat {
+    type Error = LipSyncError;
+
+    fn try_from(v: i32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(PcmFormat::U8),
+            1 => Ok(PcmFormat::S16Le),
+            2 => Ok(PcmFormat::S16Be),
+            3 => Ok(PcmFormat::S24Le),
+            4 => Ok(PcmFormat::S24Be),
+            5 => Ok(PcmFormat::S32Le),
+            6 => Ok(PcmFormat::S32Be),
+            7 => Ok(PcmFormat::F32Le),
+            8 => Ok(PcmFormat::F32Be),
+            _ => Err(LipSyncError::InvalidInput(format!(
+                "Unknown PCM format {}",
+                v
+            ))),
+        }
+    }
+}
+
+impl PcmFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            PcmFormat::U8 => 1,
+            PcmFormat::S16Le | PcmFormat::S16Be => 2,
+            PcmFormat::S24Le | PcmFormat::S24Be => 3,
+            PcmFormat::S32Le | PcmFormat::S32Be | PcmFormat::F32Le | PcmFormat::F32Be => 4,
+        }
+    }
+
+    fn decode_sample(&self, b: &[u8]) -> f32 {
+        match self {
+            PcmFormat::U8 => (b[0] as f32 - 128.0) / 128.0,
+            PcmFormat::S16Le => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            PcmFormat::S16Be => i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.0,
+            // Place the 3 bytes in the top of an i32 so the sign is extended by the shift
+            PcmFormat::S24Le => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+            PcmFormat::S24Be => (i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8) as f32 / 8388608.0,
+            PcmFormat::S32Le => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            PcmFormat::S32Be => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            PcmFormat::F32Le => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            PcmFormat::F32Be => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+}
+
+/// Decodes interleaved PCM bytes into samples in the -1..1 range. The data must contain whole
+/// frames of `channels` samples.
+pub fn decode(bytes: &[u8], format: PcmFormat, channels: usize) -> Result<Vec<f32>, LipSyncError> {
+    let frame_size = format.bytes_per_sample() * channels;
+    if channels == 0 || !bytes.len().is_multiple_of(frame_size) {
+        return Err(LipSyncError::InvalidInput(format!(
+            "{} bytes is not a whole number of {:?} frames with {} channels",
+            bytes.len(),
+            format,
+            channels
+        )));
+    }
+
+    Ok(bytes
+        .chunks_exact(format.bytes_per_sample())
+        .map(|b| format.decode_sample(b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_format() {
+        let cases: [(PcmFormat, Vec<u8>, f32); 9] = [
+            (PcmFormat::U8, vec![0, 192], -1.0),
+            (PcmFormat::S16Le, vec![0x00, 0x80, 0x00, 0x40], -1.0),
+            (PcmFormat::S16Be, vec![0x80, 0x00, 0x40, 0x00], -1.0),
+            (
+                PcmFormat::S24Le,
+                vec![0x00, 0x00, 0x80, 0x00, 0x00, 0x40],
+                -1.0,
+            ),
+            (
+                PcmFormat::S24Be,
+                vec![0x80, 0x00, 0x00, 0x40, 0x00, 0x00],
+                -1.0,
+            ),
+            (
+                PcmFormat::S32Le,
+                [i32::MIN.to_le_bytes(), (1i32 << 30).to_le_bytes()].concat(),
+                -1.0,
+            ),
+            (
+                PcmFormat::S32Be,
+                [i32::MIN.to_be_bytes(), (1i32 << 30).to_be_bytes()].concat(),
+                -1.0,
+            ),
+            (
+                PcmFormat::F32Le,
+                [(-1.0f32).to_le_bytes(), 0.5f32.to_le_bytes()].concat(),
+                -1.0,
+            ),
+            (
+                PcmFormat::F32Be,
+                [(-1.0f32).to_be_bytes(), 0.5f32.to_be_bytes()].concat(),
+                -1.0,
+            ),
+        ];
+
+        for (format, bytes, first) in cases {
+            let samples = decode(&bytes, format, 1).unwrap();
+            assert_eq!(samples, vec![first, 0.5], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn sign_extends_24_bit_samples() {
+        let samples = decode(&[0xff, 0xff, 0xff, 0x01, 0x00, 0x00], PcmFormat::S24Le, 1).unwrap();
+
+        assert_eq!(samples, vec![-1.0 / 8388608.0, 1.0 / 8388608.0]);
+    }
+
+    #[test]
+    fn keeps_channels_interleaved() {
+        let bytes = [0x00, 0x40, 0x00, 0xc0];
+
+        let samples = decode(&bytes, PcmFormat::S16Le, 2).unwrap();
+
+        assert_eq!(samples, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn rejects_partial_frames() {
+        assert!(decode(&[0, 0, 0], PcmFormat::S16Le, 1).is_err());
+        assert!(decode(&[0, 0, 0, 0, 0, 0], PcmFormat::S16Le, 2).is_err());
+        assert!(decode(&[0, 0], PcmFormat::S16Le, 0).is_err());
+        assert_eq!(decode(&[], PcmFormat::F32Le, 2).unwrap(), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn maps_format_codes() {
+        assert_eq!(PcmFormat::try_from(0).unwrap(), PcmFormat::U8);
+        assert_eq!(PcmFormat::try_from(8).unwrap(), PcmFormat::F32Be);
+        assert!(matches!(
+            PcmFormat::try_from(9),
+            Err(LipSyncError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,582 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::analysis::{
+    debug::DebugFrame,
+    error::LipSyncError,
+    job::{Frame, Job, JobMessage, Supervisor},
+    log,
+    stats::JobStats,
+    tap::TapReader,
+};
+
+pub type StreamId = u64;
+
+/// Upper bound on worker threads, lip sync frames are cheap so more than this is wasted.
+const MAX_WORKERS: usize = 4;
+/// Input frames a stream can queue before its `OverflowPolicy` kicks in.
+pub const DEFAULT_INPUT_CAPACITY: usize = 8;
+/// Results a stream can hold before new ones are dropped.
+pub const DEFAULT_OUTPUT_CAPACITY: usize = 16;
+/// How often idle workers check bus taps for new audio.
+const TAP_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+lazy_static! {
+    static ref POOL: Mutex<Option<Pool>> = Mutex::new(None);
+}
+
+/// What to do with input frames once a stream's inbox is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued frame to make room for the new one
+    DropOldest,
+    /// Discard the new frame
+    DropNewest,
+    /// Discard every queued frame, keeping only the new one
+    CoalesceLatest,
+}
+
+impl TryFrom<i32> for OverflowPolicy {
+    type Error = LipSyncError;
+
+    fn try_from(v: i32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(OverflowPolicy::DropOldest),
+            1 => Ok(OverflowPolicy::DropNewest),
+            2 => Ok(OverflowPolicy::CoalesceLatest),
+            _ => Err(LipSyncError::Configuration(format!(
+                "overflow_policy must be 0 (drop oldest), 1 (drop newest) or 2 (coalesce), got {}",
+                v
+            ))),
+        }
+    }
+}
+
+impl From<OverflowPolicy> for i32 {
+    fn from(v: OverflowPolicy) -> Self {
+        match v {
+            OverflowPolicy::DropOldest => 0,
+            OverflowPolicy::DropNewest => 1,
+            OverflowPolicy::CoalesceLatest => 2,
+        }
+    }
+}
+
+/// Frames dropped by a stream, readable without taking the pool lock.
+#[derive(Debug, Default)]
+pub struct DropCounters {
+    pub inputs: AtomicU64,
+    pub outputs: AtomicU64,
+}
+
+/// Per-stream state. The `Job` and tap are taken out while a worker is processing the stream so
+/// that the pool lock is not held during analysis.
+struct Stream {
+    job: Option<Job>,
+    tap: Option<TapReader>,
+    /// Whether the stream reads from a tap, kept while the tap is taken out
+    tapped: bool,
+    inbox: VecDeque<JobMessage>,
+    outbox: mpsc::SyncSender<JobMessage>,
+    scheduled: bool,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: Arc<DropCounters>,
+    /// Snapshot of the job's stats, updated after every batch of messages
+    stats: Arc<Mutex<JobStats>>,
+    /// Buffers of the last analyzed frame, only captured while debugging is enabled
+    debug: Arc<Mutex<Option<DebugFrame>>>,
+    supervisor: Supervisor,
+}
+
+impl Stream {
+    /// Whether the stream has work, either queued messages or a whole frame in its tap.
+    fn has_work(&self) -> bool {
+        !self.inbox.is_empty() || self.tap.as_ref().is_some_and(|v| v.has_frame())
+    }
+
+    fn queued_inputs(&self) -> usize {
+        self.inbox
+            .iter()
+            .filter(|v| matches!(v, JobMessage::InputData(_)))
+            .count()
+    }
+
+    /// Makes room for a new input frame according to the stream's policy. Returns false if
+    /// the new frame should be dropped instead.
+    fn make_room(&mut self) -> bool {
+        if self.queued_inputs() < self.capacity {
+            return true;
+        }
+
+        let dropped = match self.policy {
+            OverflowPolicy::DropNewest => return false,
+            OverflowPolicy::DropOldest => {
+                match self
+                    .inbox
+                    .iter()
+                    .position(|v| matches!(v, JobMessage::InputData(_)))
+                {
+                    Some(i) => {
+                        self.inbox.remove(i);
+                        1
+                    }
+                    None => 0,
+                }
+            }
+            OverflowPolicy::CoalesceLatest => {
+                let before = self.inbox.len();
+                self.inbox
+                    .retain(|v| !matches!(v, JobMessage::InputData(_)));
+                before - self.inbox.len()
+            }
+        };
+        self.dropped
+            .inputs
+            .fetch_add(dropped as u64, Ordering::Relaxed);
+
+        true
+    }
+}
+
+struct State {
+    streams: HashMap<StreamId, Stream>,
+    /// Streams with pending messages, in the order they should be serviced
+    ready: VecDeque<StreamId>,
+    next_id: StreamId,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+impl State {
+    /// Schedules idle streams whose tap has a whole frame waiting.
+    fn schedule_taps(&mut self) {
+        for (id, stream) in self.streams.iter_mut() {
+            if !stream.scheduled && stream.job.is_some() && stream.has_work() {
+                stream.scheduled = true;
+                self.ready.push_back(*id);
+            }
+        }
+    }
+}
+
+/// The running workers, kept so `shutdown` can wait for them.
+struct Pool {
+    shared: Arc<Shared>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Shared {
+            state: Mutex::new(State {
+                streams: HashMap::new(),
+                ready: VecDeque::new(),
+                next_id: 0,
+                shutdown: false,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+/// Starts the pool if needed and registers a new stream with it.
+pub fn register() -> Option<StreamHandle> {
+    let shared = {
+        let mut pool = POOL.lock().ok()?;
+        match pool.as_ref() {
+            Some(v) => v.shared.clone(),
+            None => {
+                let shared = Arc::new(Shared::new());
+                let workers = spawn_workers(&shared);
+                if workers.is_empty() {
+                    return None;
+                }
+                *pool = Some(Pool {
+                    shared: shared.clone(),
+                    workers,
+                });
+                shared
+            }
+        }
+    };
+
+    let (s, r) = mpsc::sync_channel(DEFAULT_OUTPUT_CAPACITY);
+    let dropped = Arc::new(DropCounters::default());
+    let stats = Arc::new(Mutex::new(JobStats::default()));
+    let debug = Arc::new(Mutex::new(None));
+
+    let id = {
+        let mut state = shared.state.lock().ok()?;
+        let id = state.next_id;
+        state.next_id += 1;
+        state.streams.insert(
+            id,
+            Stream {
+                job: Some(Job::new()),
+                tap: None,
+                tapped: false,
+                inbox: VecDeque::new(),
+                outbox: s,
+                scheduled: false,
+                capacity: DEFAULT_INPUT_CAPACITY,
+                policy: OverflowPolicy::DropOldest,
+                dropped: dropped.clone(),
+                stats: stats.clone(),
+                debug: debug.clone(),
+                supervisor: Supervisor::new(),
+            },
+        );
+        id
+    };
+
+    Some(StreamHandle {
+        id,
+        shared,
+        receiver: r,
+        dropped,
+        stats,
+        debug,
+    })
+}
+
+/// Stops all workers and waits for them to finish the frames they are analyzing, so no pool
+/// code runs once this returns. Streams registered afterwards will start a new pool.
+pub fn shutdown() {
+    let pool = match POOL.lock() {
+        Ok(mut v) => v.take(),
+        Err(_) => None,
+    };
+
+    if let Some(pool) = pool {
+        if let Ok(mut state) = pool.shared.state.lock() {
+            state.shutdown = true;
+        }
+        pool.shared.condvar.notify_all();
+
+        let current = thread::current().id();
+        for worker in pool.workers {
+            // A worker cannot wait for itself, it stops once it returns to the loop
+            if worker.thread().id() != current {
+                worker.join().ok();
+            }
+        }
+    }
+}
+
+/// Spawns up to `MAX_WORKERS` workers, returning those that could be started.
+fn spawn_workers(shared: &Arc<Shared>) -> Vec<thread::JoinHandle<()>> {
+    let count = thread::available_parallelism()
+        .map(|v| v.get())
+        .unwrap_or(1)
+        .clamp(1, MAX_WORKERS);
+
+    let mut workers = vec![];
+    for i in 0..count {
+        let shared = shared.clone();
+        let builder = thread::Builder::new().name(format!("lip-sync-worker-{}", i));
+        match builder.spawn(move || work(shared)) {
+            Ok(v) => workers.push(v),
+            Err(_) => {
+                log::print("Unable to spawn lip sync worker");
+                break;
+            }
+        }
+    }
+
+    workers
+}
+
+fn work(shared: Arc<Shared>) {
+    loop {
+        let (id, mut job, mut tap, messages, outbox, dropped, stats, debug, mut supervisor) = {
+            let mut state = match shared.state.lock() {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+
+            let id = loop {
+                if state.shutdown {
+                    return;
+                }
+                if let Some(id) = state.ready.pop_front() {
+                    break id;
+                }
+
+                // Taps are written from the audio thread, which cannot wake workers, so they
+                // are polled while any stream reads from one
+                if state.streams.values().any(|v| v.tapped) {
+                    state = match shared.condvar.wait_timeout(state, TAP_POLL_INTERVAL) {
+                        Ok((v, _)) => v,
+                        Err(_) => return,
+                    };
+                    state.schedule_taps();
+                } else {
+                    state = match shared.condvar.wait(state) {
+                        Ok(v) => v,
+                        Err(_) => return,
+                    };
+                }
+            };
+
+            let stream = match state.streams.get_mut(&id) {
+                Some(v) => v,
+                None => continue,
+            };
+            let job = match stream.job.take() {
+                Some(v) => v,
+                None => continue,
+            };
+
+            (
+                id,
+                job,
+                stream.tap.take(),
+                stream.inbox.drain(..).collect::<Vec<_>>(),
+                stream.outbox.clone(),
+                stream.dropped.clone(),
+                stream.stats.clone(),
+                stream.debug.clone(),
+                stream.supervisor,
+            )
+        };
+
+        let mut closed = false;
+        for msg in messages {
+            match msg {
+                JobMessage::InputData(d) => {
+                    if !run_frame(&mut supervisor, &mut job, &d, &outbox, &dropped) {
+                        closed = true;
+                        break;
+                    }
+                }
+                JobMessage::Configure(settings) => {
+                    if let Err(e) = job.configure(settings) {
+                        outbox.try_send(JobMessage::Error(e)).ok();
+                    }
+                }
+                JobMessage::SetProfile(profile) => job.set_profile(profile),
+                JobMessage::AttachTap(v) => tap = v,
+                JobMessage::SetDebug(v) => job.set_debug(v),
+                JobMessage::Shutdown => {
+                    closed = true;
+                    break;
+                }
+                _ => log::print("Error when matching job data"),
+            }
+        }
+
+        if let Some(reader) = tap.as_mut() {
+            reader.frame_size = job.frame_size();
+            while !closed {
+                let frame = match reader.next_frame() {
+                    Some(v) => v,
+                    None => break,
+                };
+                closed = !run_frame(&mut supervisor, &mut job, &frame, &outbox, &dropped);
+            }
+        }
+
+        if let Ok(mut v) = stats.lock() {
+            *v = job.stats.clone();
+        }
+        if let Ok(mut v) = debug.lock() {
+            *v = job.debug_frame().cloned();
+        }
+
+        let mut guard = match shared.state.lock() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let state = &mut *guard;
+        if closed {
+            state.streams.remove(&id);
+            continue;
+        }
+        if let Some(stream) = state.streams.get_mut(&id) {
+            stream.job = Some(job);
+            stream.tapped = tap.is_some();
+            stream.tap = tap;
+            stream.supervisor.frames = supervisor.frames;
+            stream.supervisor.restarts = supervisor.restarts;
+            if !stream.has_work() {
+                stream.scheduled = false;
+            } else {
+                state.ready.push_back(id);
+                shared.condvar.notify_one();
+            }
+        }
+    }
+}
+
+/// Analyzes a frame and delivers the result. Returns false once the stream should be closed.
+fn run_frame(
+    supervisor: &mut Supervisor,
+    job: &mut Job,
+    frame: &Frame,
+    outbox: &mpsc::SyncSender<JobMessage>,
+    dropped: &DropCounters,
+) -> bool {
+    let output = match supervisor.run(job, frame) {
+        Ok(v) => JobMessage::OutputData(v),
+        Err(LipSyncError::WorkerDied(e)) => {
+            // Best effort, the stream is closed either way
+            outbox
+                .try_send(JobMessage::Error(LipSyncError::WorkerDied(e)))
+                .ok();
+            return false;
+        }
+        Err(e) => JobMessage::Error(e),
+    };
+
+    match outbox.try_send(output) {
+        Ok(_) => true,
+        Err(mpsc::TrySendError::Full(_)) => {
+            dropped.outputs.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(mpsc::TrySendError::Disconnected(_)) => false,
+    }
+}
+
+/// A stream registered with the worker pool. Results for messages sent through the handle are
+/// only ever delivered to its own `receiver`. The stream is unregistered when dropped.
+pub struct StreamHandle {
+    id: StreamId,
+    shared: Arc<Shared>,
+    pub receiver: mpsc::Receiver<JobMessage>,
+    dropped: Arc<DropCounters>,
+    stats: Arc<Mutex<JobStats>>,
+    debug: Arc<Mutex<Option<DebugFrame>>>,
+}
+
+impl StreamHandle {
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    /// Input frames dropped because the inbox was full.
+    pub fn dropped_inputs(&self) -> u64 {
+        self.dropped.inputs.load(Ordering::Relaxed)
+    }
+
+    /// Results dropped because they were not polled fast enough.
+    pub fn dropped_outputs(&self) -> u64 {
+        self.dropped.outputs.load(Ordering::Relaxed)
+    }
+
+    /// Stats of the stream's job as of the last processed batch.
+    pub fn stats(&self) -> JobStats {
+        match self.stats.lock() {
+            Ok(v) => v.clone(),
+            Err(_) => JobStats::default(),
+        }
+    }
+
+    /// Buffers of the last analyzed frame as of the last processed batch, `None` unless
+    /// enabled with `JobMessage::SetDebug`.
+    pub fn debug_frame(&self) -> Option<DebugFrame> {
+        match self.debug.lock() {
+            Ok(v) => v.clone(),
+            Err(_) => None,
+        }
+    }
+
+    /// Number of input frames waiting to be analyzed.
+    pub fn queue_depth(&self) -> usize {
+        match self.shared.state.lock() {
+            Ok(state) => state.streams.get(&self.id).map_or(0, |v| v.queued_inputs()),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        self.with_stream(|v| v.capacity = capacity.max(1));
+    }
+
+    pub fn set_policy(&self, policy: OverflowPolicy) {
+        self.with_stream(|v| v.policy = policy);
+    }
+
+    /// Sets how many panics the stream recovers from before shutting down.
+    pub fn set_max_restarts(&self, max_restarts: u32) {
+        self.with_stream(|v| v.supervisor.max_restarts = max_restarts);
+    }
+
+    fn with_stream<F: FnOnce(&mut Stream)>(&self, f: F) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            if let Some(stream) = state.streams.get_mut(&self.id) {
+                f(stream);
+            }
+        }
+    }
+
+    /// Queues a message for the stream. Fails if the stream or pool has been shut down.
+    pub fn send(&self, msg: JobMessage) -> Result<(), JobMessage> {
+        let mut guard = match self.shared.state.lock() {
+            Ok(v) => v,
+            Err(_) => return Err(msg),
+        };
+        let state = &mut *guard;
+        if state.shutdown {
+            return Err(msg);
+        }
+
+        let stream = match state.streams.get_mut(&self.id) {
+            Some(v) => v,
+            None => return Err(msg),
+        };
+        if matches!(msg, JobMessage::InputData(_)) && !stream.make_room() {
+            stream.dropped.inputs.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        stream.inbox.push_back(msg);
+        if !stream.scheduled {
+            stream.scheduled = true;
+            state.ready.push_back(self.id);
+            self.shared.condvar.notify_one();
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.streams.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_overflow_policies() {
+        for policy in [
+            OverflowPolicy::DropOldest,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::CoalesceLatest,
+        ] {
+            assert_eq!(OverflowPolicy::try_from(i32::from(policy)), Ok(policy));
+        }
+        assert!(matches!(
+            OverflowPolicy::try_from(3),
+            Err(LipSyncError::Configuration(_))
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{
+    error::LipSyncError,
+    model::{DataPoint, MouthShape, Phoneme, Profile, DEFAULT_MOUTH_SHAPES},
+};
+
+/// JSON layout of a profile. Every list is indexed like `phonemes`, peaks are `[position,
+/// amplitude]` pairs and mouth shapes `[open, form]` pairs.
+#[derive(Serialize, Deserialize)]
+struct ProfileFile {
+    phonemes: Vec<String>,
+    peaks3: Vec<Vec<[f32; 2]>>,
+    peaks4: Vec<Vec<[f32; 2]>>,
+    /// Phonemes known to the default profile fall back to its mouth shapes when missing
+    #[serde(default)]
+    mouth_shapes: Vec<[f32; 2]>,
+}
+
+/// Reads a profile from JSON, checking that every phoneme has a complete set of templates.
+pub fn parse(text: &str) -> Result<Profile, LipSyncError> {
+    let file: ProfileFile =
+        serde_json::from_str(text).map_err(|e| LipSyncError::Profile(e.to_string()))?;
+
+    let to_phoneme = |v: &Vec<[f32; 2]>| Phoneme(v.iter().map(|p| DataPoint(p[0], p[1])).collect());
+    let mouth_shapes = if file.mouth_shapes.is_empty() {
+        file.phonemes
+            .iter()
+            .map(|p| {
+                DEFAULT_MOUTH_SHAPES
+                    .get(p)
+                    .copied()
+                    .unwrap_or_else(MouthShape::zero)
+            })
+            .collect()
+    } else {
+        file.mouth_shapes
+            .iter()
+            .map(|v| MouthShape::new(v[0], v[1]))
+            .collect()
+    };
+
+    let profile = Profile {
+        peaks3: file.peaks3.iter().map(to_phoneme).collect(),
+        peaks4: file.peaks4.iter().map(to_phoneme).collect(),
+        mouth_shapes,
+        phonemes: file.phonemes,
+    };
+    validate(&profile)?;
+
+    Ok(profile)
+}
+
+/// Checks that every phoneme has 3 and 4 peak templates and a mouth shape.
+pub fn validate(profile: &Profile) -> Result<(), LipSyncError> {
+    let n = profile.phonemes.len();
+    if n == 0 {
+        return Err(LipSyncError::Profile("Profile has no phonemes".to_owned()));
+    }
+    for (name, peaks, count) in [
+        ("peaks3", &profile.peaks3, 3),
+        ("peaks4", &profile.peaks4, 4),
+    ] {
+        if peaks.len() != n {
+            return Err(LipSyncError::Profile(format!(
+                "{} has {} entries for {} phonemes",
+                name,
+                peaks.len(),
+                n
+            )));
+        }
+        if let Some(i) = peaks.iter().position(|v| v.0.len() != count) {
+            return Err(LipSyncError::Profile(format!(
+                "{} of phoneme {} must have {} peaks",
+                name, profile.phonemes[i], count
+            )));
+        }
+    }
+    if profile.mouth_shapes.len() != n {
+        return Err(LipSyncError::Profile(format!(
+            "mouth_shapes has {} entries for {} phonemes",
+            profile.mouth_shapes.len(),
+            n
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn to_json(profile: &Profile) -> String {
+    let from_phoneme = |v: &Phoneme| v.0.iter().map(|p| [p.0, p.1]).collect::<Vec<_>>();
+    let file = ProfileFile {
+        phonemes: profile.phonemes.clone(),
+        peaks3: profile.peaks3.iter().map(from_phoneme).collect(),
+        peaks4: profile.peaks4.iter().map(from_phoneme).collect(),
+        mouth_shapes: profile
+            .mouth_shapes
+            .iter()
+            .map(|v| [v.open, v.form])
+            .collect(),
+    };
+
+    serde_json::to_string_pretty(&file).unwrap_or_default()
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// Lock-free single producer, single consumer ring of samples. The producer never blocks or
+/// allocates, so it is safe to push from the audio thread. Samples that do not fit are dropped
+/// and counted as overruns.
+pub struct SpscRing {
+    /// Samples stored as their bit patterns so the buffer can be shared without locks
+    buffer: Box<[AtomicU32]>,
+    mask: usize,
+    /// Next position to read, only advanced by the consumer
+    head: AtomicUsize,
+    /// Next position to write, only advanced by the producer
+    tail: AtomicUsize,
+    overruns: AtomicU64,
+}
+
+impl SpscRing {
+    /// Creates a ring holding at least `capacity` samples, rounded up to a power of two.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+
+        SpscRing {
+            buffer: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overruns: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Samples waiting to be read.
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Samples dropped because the consumer fell behind.
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Producer side. Writes as many samples as fit and returns how many were written.
+    pub fn push<I: IntoIterator<Item = f32>>(&self, samples: I) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        let free = self.capacity() - tail.wrapping_sub(head);
+
+        let mut written = 0;
+        let mut dropped = 0;
+        for sample in samples {
+            if written < free {
+                self.buffer[tail & self.mask].store(sample.to_bits(), Ordering::Relaxed);
+                tail = tail.wrapping_add(1);
+                written += 1;
+            } else {
+                dropped += 1;
+            }
+        }
+
+        self.tail.store(tail, Ordering::Release);
+        if dropped > 0 {
+            self.overruns.fetch_add(dropped, Ordering::Relaxed);
+        }
+
+        written
+    }
+
+    /// Consumer side. Appends up to `count` samples to `out` and returns how many were read.
+    pub fn pop_into(&self, out: &mut Vec<f32>, count: usize) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let mut head = self.head.load(Ordering::Relaxed);
+        let count = count.min(tail.wrapping_sub(head));
+
+        out.reserve(count);
+        for _ in 0..count {
+            out.push(f32::from_bits(
+                self.buffer[head & self.mask].load(Ordering::Relaxed),
+            ));
+            head = head.wrapping_add(1);
+        }
+        self.head.store(head, Ordering::Release);
+
+        count
+    }
+
+    /// Consumer side. Discards everything written so far.
+    pub fn clear(&self) {
+        let tail = self.tail.load(Ordering::Acquire);
+        self.head.store(tail, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn rounds_capacity_up() {
+        assert_eq!(SpscRing::new(0).capacity(), 2);
+        assert_eq!(SpscRing::new(5).capacity(), 8);
+        assert_eq!(SpscRing::new(8).capacity(), 8);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let ring = SpscRing::new(4);
+        let mut out = vec![];
+
+        for i in 0..10 {
+            assert_eq!(ring.push([i as f32, i as f32 + 0.5]), 2);
+            assert_eq!(ring.len(), 2);
+            assert_eq!(ring.pop_into(&mut out, 2), 2);
+        }
+
+        assert_eq!(out.len(), 20);
+        assert_eq!(&out[18..], [9.0, 9.5]);
+        assert_eq!(ring.overruns(), 0);
+    }
+
+    #[test]
+    fn drops_and_counts_overruns() {
+        let ring = SpscRing::new(4);
+        let mut out = vec![];
+
+        assert_eq!(ring.push([1.0, 2.0, 3.0]), 3);
+        assert_eq!(ring.push([4.0, 5.0, 6.0]), 1);
+        assert_eq!(ring.overruns(), 2);
+
+        assert_eq!(ring.pop_into(&mut out, 10), 4);
+        assert_eq!(out, vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(ring.pop_into(&mut out, 10), 0);
+    }
+
+    #[test]
+    fn clear_discards_pending_samples() {
+        let ring = SpscRing::new(4);
+        let mut out = vec![];
+        ring.push([1.0, 2.0, 3.0]);
+
+        ring.clear();
+
+        assert!(ring.is_empty());
+        ring.push([4.0]);
+        ring.pop_into(&mut out, 4);
+        assert_eq!(out, vec![4.0]);
+    }
+
+    #[test]
+    fn keeps_order_across_threads() {
+        let ring = Arc::new(SpscRing::new(64));
+        let producer = {
+            let ring = ring.clone();
+            thread::spawn(move || {
+                let mut next = 0;
+                while next < 10_000 {
+                    next += ring.push((next..(next + 7).min(10_000)).map(|v| v as f32));
+                }
+            })
+        };
+
+        let mut out = vec![];
+        while out.len() < 10_000 {
+            ring.pop_into(&mut out, 5);
+        }
+        producer.join().unwrap();
+
+        assert!(out.iter().enumerate().all(|(i, v)| *v == i as f32));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::analysis::{error::LipSyncError, model::*};
+
+/// How peak positions are compared against each phoneme's template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareMethod {
+    /// Sum of absolute differences, the original metric
+    L1,
+    /// Euclidean distance
+    L2,
+    /// One minus the cosine similarity, ignoring overall loudness
+    Cosine,
+    /// A value matching no method, rejected by `LipSyncSettings::validate`
+    Unknown(i32),
+}
+
+impl From<i32> for CompareMethod {
+    fn from(v: i32) -> Self {
+        match v {
+            0 => CompareMethod::L1,
+            1 => CompareMethod::L2,
+            2 => CompareMethod::Cosine,
+            v => CompareMethod::Unknown(v),
+        }
+    }
+}
+
+impl From<CompareMethod> for i32 {
+    fn from(v: CompareMethod) -> Self {
+        match v {
+            CompareMethod::L1 => 0,
+            CompareMethod::L2 => 1,
+            CompareMethod::Cosine => 2,
+            CompareMethod::Unknown(v) => v,
+        }
+    }
+}
+
+/// Tuning for the analysis pipeline. Can be changed while a stream is running, the job applies
+/// new settings between frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LipSyncSettings {
+    /// Samples used per analysis, must be a power of 2
+    pub fft_samples: usize,
+    /// Loudness range in dB mapped to an amount of 0 to 1
+    pub dynamic_range: f32,
+    /// Minimum height of a spectral envelope peak, relative to the highest point
+    pub peak_threshold: f32,
+    /// Number of cepstral coefficients kept when smoothing the spectrum
+    pub lifter_level: i32,
+    /// Values at or below this are cut from the spectrum, at most `fft_samples / 2`
+    pub filter_low: i32,
+    /// Values at or above this are cut from the spectrum, at most `fft_samples / 2`
+    pub filter_high: i32,
+    pub compare_method: CompareMethod,
+    /// Seconds for the output to follow a rising amount, 0 to follow immediately
+    pub attack_time: f32,
+    /// Seconds for the output to follow a falling amount, 0 to follow immediately
+    pub release_time: f32,
+}
+
+impl Default for LipSyncSettings {
+    fn default() -> Self {
+        LipSyncSettings {
+            fft_samples: FFT_SAMPLES,
+            dynamic_range: DYNAMIC_RANGE,
+            peak_threshold: 0.1,
+            lifter_level: 26,
+            filter_low: 10,
+            filter_high: 95,
+            compare_method: CompareMethod::L1,
+            attack_time: 0.0,
+            release_time: 0.0,
+        }
+    }
+}
+
+impl LipSyncSettings {
+    pub fn validate(&self) -> Result<(), LipSyncError> {
+        if !self.fft_samples.is_power_of_two() || !(64..=8192).contains(&self.fft_samples) {
+            return Err(LipSyncError::Configuration(format!(
+                "fft_samples must be a power of 2 between 64 and 8192, got {}",
+                self.fft_samples
+            )));
+        }
+        if !self.dynamic_range.is_finite() || self.dynamic_range <= 0.0 {
+            return Err(LipSyncError::Configuration(format!(
+                "dynamic_range must be positive and finite, got {}",
+                self.dynamic_range
+            )));
+        }
+        if !(0.0..1.0).contains(&self.peak_threshold) {
+            return Err(LipSyncError::Configuration(format!(
+                "peak_threshold must be between 0 and 1, got {}",
+                self.peak_threshold
+            )));
+        }
+        if self.lifter_level < 1 || self.lifter_level as usize >= self.fft_samples / 4 {
+            return Err(LipSyncError::Configuration(format!(
+                "lifter_level must be between 1 and {}, got {}",
+                self.fft_samples / 4 - 1,
+                self.lifter_level
+            )));
+        }
+        let nyquist = (self.fft_samples / 2) as i32;
+        if self.filter_low < 0 || self.filter_high > nyquist {
+            return Err(LipSyncError::Configuration(format!(
+                "filter_low ({}) and filter_high ({}) must be between 0 and {}, the Nyquist bin",
+                self.filter_low, self.filter_high, nyquist
+            )));
+        }
+        if self.filter_low >= self.filter_high {
+            return Err(LipSyncError::Configuration(format!(
+                "filter_low ({}) must be lower than filter_high ({})",
+                self.filter_low, self.filter_high
+            )));
+        }
+        if let CompareMethod::Unknown(v) = self.compare_method {
+            return Err(LipSyncError::Configuration(format!(
+                "compare_method must be 0 (L1), 1 (L2) or 2 (cosine), got {}",
+                v
+            )));
+        }
+        let times = [
+            ("attack_time", self.attack_time),
+            ("release_time", self.release_time),
+        ];
+        for (name, time) in times {
+            if time.is_nan() || time < 0.0 {
+                return Err(LipSyncError::Configuration(format!(
+                    "{} must not be negative, got {}",
+                    name, time
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(f: impl FnOnce(&mut LipSyncSettings)) -> Result<(), LipSyncError> {
+        let mut settings = LipSyncSettings::default();
+        f(&mut settings);
+        settings.validate()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(LipSyncSettings::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn accepts_edge_values() {
+        assert!(with(|v| v.fft_samples = 64).is_err());
+        assert!(with(|v| {
+            v.fft_samples = 64;
+            v.lifter_level = 15;
+            v.filter_high = 32;
+        })
+        .is_ok());
+        assert!(with(|v| v.fft_samples = 8192).is_ok());
+        assert!(with(|v| v.peak_threshold = 0.0).is_ok());
+        assert!(with(|v| v.filter_low = 0).is_ok());
+        assert!(with(|v| v.compare_method = CompareMethod::Cosine).is_ok());
+        assert!(with(|v| v.release_time = 0.5).is_ok());
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let cases: [fn(&mut LipSyncSettings); 16] = [
+            |v| v.fft_samples = 1000,
+            |v| v.fft_samples = 32,
+            |v| v.fft_samples = 16384,
+            |v| v.dynamic_range = 0.0,
+            |v| v.dynamic_range = f32::NAN,
+            |v| v.dynamic_range = f32::INFINITY,
+            |v| v.peak_threshold = 1.0,
+            |v| v.lifter_level = 0,
+            |v| v.lifter_level = 256,
+            |v| v.filter_low = v.filter_high,
+            |v| v.filter_low = v.filter_high + 1,
+            |v| v.filter_low = -1,
+            |v| v.filter_high = 513,
+            |v| v.compare_method = CompareMethod::Unknown(3),
+            |v| v.attack_time = -0.1,
+            |v| v.release_time = f32::NAN,
+        ];
+
+        for (i, case) in cases.into_iter().enumerate() {
+            assert!(
+                matches!(with(case), Err(LipSyncError::Configuration(_))),
+                "case {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn maps_compare_methods() {
+        for method in [CompareMethod::L1, CompareMethod::L2, CompareMethod::Cosine] {
+            assert_eq!(CompareMethod::from(i32::from(method)), method);
+        }
+        assert_eq!(CompareMethod::from(-1), CompareMethod::Unknown(-1));
+        assert_eq!(i32::from(CompareMethod::Unknown(7)), 7);
+    }
+}
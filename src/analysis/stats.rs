@@ -0,0 +1,93 @@
+use std::{
+    ops::AddAssign,
+    time::{Duration, Instant},
+};
+
+/// Names of the timed pipeline stages, in the order they run.
+pub const STAGES: [&str; 5] = ["windowing", "fft", "cepstrum", "peaks", "classification"];
+pub const STAGE_WINDOWING: usize = 0;
+pub const STAGE_FFT: usize = 1;
+pub const STAGE_CEPSTRUM: usize = 2;
+pub const STAGE_PEAKS: usize = 3;
+pub const STAGE_CLASSIFICATION: usize = 4;
+
+/// Time spent in each stage of the pipeline, indexed like `STAGES`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StageTimings(pub [Duration; 5]);
+
+impl StageTimings {
+    pub fn total(&self) -> Duration {
+        self.0.iter().sum()
+    }
+}
+
+impl AddAssign for StageTimings {
+    fn add_assign(&mut self, other: StageTimings) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += *b;
+        }
+    }
+}
+
+/// Measures consecutive stages of a single frame.
+pub struct StageTimer {
+    timings: StageTimings,
+    last: Instant,
+}
+
+impl StageTimer {
+    pub fn start() -> Self {
+        StageTimer {
+            timings: StageTimings::default(),
+            last: Instant::now(),
+        }
+    }
+
+    /// Ends the current stage, attributing the time since the previous call to `stage`.
+    pub fn lap(&mut self, stage: usize) {
+        let now = Instant::now();
+        self.timings.0[stage] += now - self.last;
+        self.last = now;
+    }
+
+    pub fn finish(self) -> StageTimings {
+        self.timings
+    }
+}
+
+/// Counters for a job, kept across resets.
+#[derive(Debug, Default, Clone)]
+pub struct JobStats {
+    pub frames_processed: u64,
+    /// Frames that were not analyzed, because they were too small or dropped from a full queue
+    pub frames_skipped: u64,
+    /// Frames whose queue wait was recorded
+    pub frames_waited: u64,
+    pub last: StageTimings,
+    pub total: StageTimings,
+    /// Time the last frame spent queued before being analyzed
+    pub queue_wait_last: Duration,
+    pub queue_wait_total: Duration,
+}
+
+impl JobStats {
+    pub fn record_frame(&mut self, timings: StageTimings) {
+        self.frames_processed += 1;
+        self.last = timings;
+        self.total += timings;
+    }
+
+    pub fn record_wait(&mut self, wait: Duration) {
+        self.frames_waited += 1;
+        self.queue_wait_last = wait;
+        self.queue_wait_total += wait;
+    }
+
+    pub fn average(&self, total: Duration, count: u64) -> f64 {
+        if count == 0 {
+            0.0
+        } else {
+            total.as_secs_f64() * 1_000_000.0 / count as f64
+        }
+    }
+}
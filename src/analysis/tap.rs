@@ -0,0 +1,179 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::analysis::{
+    input::{downmix, ChannelMode},
+    job::Frame,
+    log,
+    ring::SpscRing,
+};
+
+/// Channels written by bus taps, Godot buses are always mixed as stereo.
+pub const TAP_CHANNELS: usize = 2;
+/// Interleaved samples a tap can hold, about 0.7 seconds at 48kHz.
+const TAP_CAPACITY: usize = 1 << 16;
+
+lazy_static! {
+    static ref TAPS: Mutex<HashMap<String, Arc<Tap>>> = Mutex::new(HashMap::new());
+}
+
+/// Audio forwarded from a bus to a lip sync stream, shared by name so the effect and the node
+/// can be created in any order.
+pub struct Tap {
+    ring: SpscRing,
+    /// Set while an effect instance is writing, a bus may instantiate an effect more than once
+    producing: AtomicBool,
+    /// Set while a reader is consuming, the ring only supports one
+    consuming: AtomicBool,
+}
+
+impl Tap {
+    fn new() -> Self {
+        Tap {
+            ring: SpscRing::new(TAP_CAPACITY),
+            producing: AtomicBool::new(false),
+            consuming: AtomicBool::new(false),
+        }
+    }
+
+    /// Samples dropped because no stream was reading fast enough.
+    pub fn overruns(&self) -> u64 {
+        self.ring.overruns()
+    }
+}
+
+/// Returns the tap called `name`, creating it if needed. Taps nobody holds anymore are removed.
+pub fn get(name: &str) -> Arc<Tap> {
+    let mut taps = match TAPS.lock() {
+        Ok(v) => v,
+        Err(e) => e.into_inner(),
+    };
+    taps.retain(|k, v| k == name || Arc::strong_count(v) > 1);
+
+    taps.entry(name.to_owned())
+        .or_insert_with(|| Arc::new(Tap::new()))
+        .clone()
+}
+
+/// Producer side of a tap, used from the audio thread.
+pub struct TapWriter {
+    tap: Arc<Tap>,
+}
+
+impl TapWriter {
+    /// Claims the tap for writing. Returns `None` if another writer already holds it.
+    pub fn new(tap: Arc<Tap>) -> Option<Self> {
+        if tap.producing.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        Some(TapWriter { tap })
+    }
+
+    /// Writes interleaved stereo samples without blocking.
+    pub fn write<I: IntoIterator<Item = f32>>(&self, samples: I) {
+        self.tap.ring.push(samples);
+    }
+}
+
+impl Drop for TapWriter {
+    fn drop(&mut self) {
+        self.tap.producing.store(false, Ordering::Release);
+    }
+}
+
+/// Consumer side of a tap. Cuts the tapped audio into mono frames of `frame_size` samples,
+/// timestamped from its first read.
+///
+/// A tap has a single consumer. The reader claims the tap on its first read, from the thread
+/// doing the reading, and releases it when dropped, so a reader can be replaced by sending the
+/// new one to that thread. Readers created while another one holds the tap never get audio.
+pub struct TapReader {
+    tap: Arc<Tap>,
+    pub channel_mode: ChannelMode,
+    pub frame_size: usize,
+    sample_rate: f64,
+    /// Mono samples read so far
+    position: u64,
+    /// Whether this reader is the tap's consumer
+    claimed: bool,
+    /// Whether finding the tap held by another reader was reported
+    reported: bool,
+}
+
+impl TapReader {
+    /// Prepares to read from the tap. Audio written before the first read is discarded.
+    pub fn new(tap: Arc<Tap>, channel_mode: ChannelMode, sample_rate: f64) -> Self {
+        TapReader {
+            tap,
+            channel_mode,
+            frame_size: 0,
+            sample_rate,
+            position: 0,
+            claimed: false,
+            reported: false,
+        }
+    }
+
+    /// Whether another reader holds the tap, so this one gets no audio.
+    pub fn is_blocked(&self) -> bool {
+        !self.claimed && self.tap.consuming.load(Ordering::Acquire)
+    }
+
+    /// Whether a whole frame is waiting to be read.
+    pub fn has_frame(&self) -> bool {
+        self.frame_size > 0
+            && !self.is_blocked()
+            && self.tap.ring.len() >= self.frame_size * TAP_CHANNELS
+    }
+
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        if !self.claim() || !self.has_frame() {
+            return None;
+        }
+
+        let mut interleaved = Vec::with_capacity(self.frame_size * TAP_CHANNELS);
+        self.tap
+            .ring
+            .pop_into(&mut interleaved, self.frame_size * TAP_CHANNELS);
+        let samples = downmix(interleaved.as_slice(), TAP_CHANNELS, self.channel_mode);
+
+        let start_time = self.position as f64 / self.sample_rate;
+        self.position += samples.len() as u64;
+
+        Some(Frame::new(samples, start_time, self.sample_rate))
+    }
+
+    /// Becomes the tap's consumer unless another reader is, discarding stale audio.
+    fn claim(&mut self) -> bool {
+        if self.claimed {
+            return true;
+        }
+        if self.tap.consuming.swap(true, Ordering::AcqRel) {
+            if !self.reported {
+                self.reported = true;
+                log::print("Bus tap is already read by another stream, only one reader is allowed");
+            }
+            return false;
+        }
+
+        // Only the consumer may move the head, which is now this thread
+        self.tap.ring.clear();
+        self.claimed = true;
+
+        true
+    }
+}
+
+impl Drop for TapReader {
+    fn drop(&mut self) {
+        if self.claimed {
+            self.tap.consuming.store(false, Ordering::Release);
+        }
+    }
+}
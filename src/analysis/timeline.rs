@@ -0,0 +1,231 @@
+use crate::analysis::model::{MouthShape, VowelEstimate};
+
+/// Mouth state at a point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframe {
+    /// Seconds from the start of the audio
+    pub time: f64,
+    /// Index into the timeline's phonemes, -1 for silence
+    pub vowel: i32,
+    pub amount: f32,
+    /// Per-phoneme weights, indexed like the timeline's phonemes
+    pub weights: Vec<f32>,
+    pub mouth: MouthShape,
+}
+
+impl From<&VowelEstimate> for Keyframe {
+    fn from(ve: &VowelEstimate) -> Self {
+        Keyframe {
+            time: ve.start_time,
+            vowel: ve.vowel,
+            amount: ve.amount,
+            weights: ve.weights.clone(),
+            mouth: ve.mouth,
+        }
+    }
+}
+
+impl From<&Keyframe> for VowelEstimate {
+    fn from(k: &Keyframe) -> Self {
+        let mut ve = VowelEstimate::new(k.vowel, k.vowel, k.amount);
+        ve.weights = k.weights.clone();
+        ve.mouth = k.mouth;
+        ve.start_time = k.time;
+        ve.end_time = k.time;
+        ve
+    }
+}
+
+/// Precomputed mouth animation, with keyframes sorted by time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    pub phonemes: Vec<String>,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Timeline {
+    pub fn new(phonemes: Vec<String>) -> Self {
+        Timeline {
+            phonemes,
+            keyframes: vec![],
+        }
+    }
+
+    /// Time of the last keyframe.
+    pub fn length(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |v| v.time)
+    }
+
+    /// Mouth state at `time`. Amounts, weights and mouth parameters are interpolated between
+    /// the surrounding keyframes while the vowel is held from the previous one. Times outside
+    /// the timeline return the first or last keyframe.
+    pub fn sample(&self, time: f64) -> Option<Keyframe> {
+        let next = self.keyframes.partition_point(|v| v.time <= time);
+        if next == 0 {
+            return self.keyframes.first().cloned();
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes.last().cloned();
+        }
+
+        let a = &self.keyframes[next - 1];
+        let b = &self.keyframes[next];
+        let f = if b.time > a.time {
+            ((time - a.time) / (b.time - a.time)) as f32
+        } else {
+            0.0
+        };
+        let lerp = |x: f32, y: f32| x + f * (y - x);
+
+        Some(Keyframe {
+            time,
+            vowel: a.vowel,
+            amount: lerp(a.amount, b.amount),
+            weights: a
+                .weights
+                .iter()
+                .zip(b.weights.iter())
+                .map(|(x, y)| lerp(*x, *y))
+                .collect(),
+            mouth: MouthShape::new(
+                lerp(a.mouth.open, b.mouth.open),
+                lerp(a.mouth.form, b.mouth.form),
+            ),
+        })
+    }
+}
+
+/// Removes keys that can be linearly interpolated from their neighbours with an error of at
+/// most `tolerance`, using Ramer-Douglas-Peucker. The first and last keys are always kept.
+pub fn reduce_keys(keys: &[(f64, f32)], tolerance: f32) -> Vec<(f64, f32)> {
+    let n = keys.len();
+    if n <= 2 {
+        return keys.to_vec();
+    }
+
+    let mut keep = vec![false; n];
+    keep[0] = true;
+    keep[n - 1] = true;
+
+    let mut segments = vec![(0, n - 1)];
+    while let Some((start, end)) = segments.pop() {
+        let (t0, v0) = keys[start];
+        let (t1, v1) = keys[end];
+
+        let mut max_error = 0.0;
+        let mut max_idx = start;
+        for (i, (t, v)) in keys.iter().enumerate().take(end).skip(start + 1) {
+            let f = if t1 > t0 {
+                ((t - t0) / (t1 - t0)) as f32
+            } else {
+                0.0
+            };
+            let error = (v - (v0 + f * (v1 - v0))).abs();
+            if error > max_error {
+                max_error = error;
+                max_idx = i;
+            }
+        }
+
+        if max_error > tolerance {
+            keep[max_idx] = true;
+            segments.push((start, max_idx));
+            segments.push((max_idx, end));
+        }
+    }
+
+    keys.iter()
+        .zip(keep.iter())
+        .filter(|(_, k)| **k)
+        .map(|(v, _)| *v)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f64, vowel: i32, amount: f32) -> Keyframe {
+        Keyframe {
+            time,
+            vowel,
+            amount,
+            weights: vec![amount, 1.0 - amount],
+            mouth: MouthShape::new(amount, 0.0),
+        }
+    }
+
+    fn timeline() -> Timeline {
+        let mut timeline = Timeline::new(vec!["A".into(), "I".into()]);
+        timeline.keyframes = vec![keyframe(1.0, 0, 0.0), keyframe(2.0, 1, 1.0)];
+        timeline
+    }
+
+    #[test]
+    fn sample_interpolates_between_keyframes() {
+        let v = timeline().sample(1.25).unwrap();
+
+        assert_eq!(v.time, 1.25);
+        assert_eq!(v.vowel, 0);
+        assert_eq!(v.amount, 0.25);
+        assert_eq!(v.weights, vec![0.25, 0.75]);
+        assert_eq!(v.mouth, MouthShape::new(0.25, 0.0));
+    }
+
+    #[test]
+    fn sample_on_a_keyframe_returns_it() {
+        let timeline = timeline();
+
+        assert_eq!(timeline.sample(1.0).unwrap(), timeline.keyframes[0]);
+        assert_eq!(timeline.sample(2.0).unwrap(), timeline.keyframes[1]);
+    }
+
+    #[test]
+    fn sample_outside_range_clamps() {
+        let timeline = timeline();
+
+        assert_eq!(timeline.sample(-5.0).unwrap(), timeline.keyframes[0]);
+        assert_eq!(timeline.sample(0.5).unwrap(), timeline.keyframes[0]);
+        assert_eq!(timeline.sample(9.0).unwrap(), timeline.keyframes[1]);
+    }
+
+    #[test]
+    fn sample_empty_timeline() {
+        let timeline = Timeline::new(vec![]);
+
+        assert!(timeline.sample(0.0).is_none());
+        assert_eq!(timeline.length(), 0.0);
+    }
+
+    #[test]
+    fn reduce_keeps_short_lists() {
+        let keys = [(0.0, 1.0), (1.0, 0.0)];
+
+        assert_eq!(reduce_keys(&keys, 1.0), keys.to_vec());
+        assert!(reduce_keys(&[], 0.0).is_empty());
+    }
+
+    #[test]
+    fn reduce_drops_collinear_keys() {
+        let keys = [(0.0, 0.0), (1.0, 0.5), (2.0, 1.0), (4.0, 2.0)];
+
+        assert_eq!(reduce_keys(&keys, 0.0), vec![(0.0, 0.0), (4.0, 2.0)]);
+    }
+
+    #[test]
+    fn reduce_with_zero_tolerance_keeps_every_corner() {
+        let keys = [(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (3.0, 0.0), (4.0, 0.25)];
+
+        assert_eq!(reduce_keys(&keys, 0.0), keys.to_vec());
+    }
+
+    #[test]
+    fn reduce_keeps_keys_above_tolerance() {
+        let keys = [(0.0, 0.0), (1.0, 0.55), (2.0, 1.0), (3.0, 0.0), (4.0, 0.0)];
+
+        assert_eq!(
+            reduce_keys(&keys, 0.1),
+            vec![(0.0, 0.0), (2.0, 1.0), (3.0, 0.0), (4.0, 0.0)]
+        );
+    }
+}
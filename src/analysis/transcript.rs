@@ -0,0 +1,355 @@
+use crate::analysis::{model::Profile, timeline::Timeline};
+
+/// How strongly baked keyframes are pulled towards the transcript by default.
+pub const DEFAULT_TRANSCRIPT_STRENGTH: f32 = 0.8;
+/// Keyframes quieter than this count as silence and are not aligned.
+pub const SILENCE_AMOUNT: f32 = 0.1;
+
+/// Text of a baked line, with the rules reading its vowels and how strongly keyframes are
+/// pulled towards them, 0 keeping the analysis and 1 forcing the transcript.
+pub struct Transcript<'a> {
+    pub text: &'a str,
+    pub rules: &'a dyn VowelRules,
+    pub strength: f32,
+}
+
+/// Turns text into the sequence of phonemes it is expected to produce.
+pub trait VowelRules: Send + Sync {
+    fn vowels(&self, text: &str) -> Vec<&'static str>;
+}
+
+/// Grapheme to vowel rules applied greedily from left to right in every word. Graphemes are
+/// tried in table order, so longer ones must come first. An empty vowel consumes the grapheme
+/// without producing anything, and letters matching no rule are skipped.
+pub struct GraphemeRules {
+    pub graphemes: &'static [(&'static str, &'static str)],
+    /// Ignores a word's final `e` when the word has more than three letters, as in English
+    pub silent_final_e: bool,
+}
+
+impl VowelRules for GraphemeRules {
+    fn vowels(&self, text: &str) -> Vec<&'static str> {
+        let text = text.to_lowercase();
+        let mut out = vec![];
+
+        for word in text
+            .split(|c: char| !c.is_alphabetic())
+            .filter(|v| !v.is_empty())
+        {
+            let word = match word.strip_suffix('e') {
+                Some(v) if self.silent_final_e && word.chars().count() > 3 => v,
+                _ => word,
+            };
+
+            let mut rest = word;
+            while let Some(c) = rest.chars().next() {
+                match self.graphemes.iter().find(|(g, _)| rest.starts_with(g)) {
+                    Some((g, v)) => {
+                        if !v.is_empty() {
+                            out.push(*v);
+                        }
+                        rest = &rest[g.len()..];
+                    }
+                    None => rest = &rest[c.len_utf8()..],
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Rough English spelling rules, good enough to keep the order of vowels in a line.
+pub const ENGLISH: GraphemeRules = GraphemeRules {
+    graphemes: &[
+        ("igh", "A"),
+        ("eau", "O"),
+        ("qu", ""),
+        ("ee", "I"),
+        ("ea", "I"),
+        ("ie", "I"),
+        ("ei", "E"),
+        ("ai", "E"),
+        ("ay", "E"),
+        ("ey", "E"),
+        ("oo", "U"),
+        ("ew", "U"),
+        ("ue", "U"),
+        ("ou", "O"),
+        ("ow", "O"),
+        ("oa", "O"),
+        ("oi", "O"),
+        ("oy", "O"),
+        ("au", "O"),
+        ("aw", "O"),
+        ("a", "A"),
+        ("e", "E"),
+        ("i", "I"),
+        ("o", "O"),
+        ("u", "U"),
+        ("y", "I"),
+    ],
+    silent_final_e: true,
+};
+
+/// Spanish spelling is close to phonetic, only the silent `u` after `q` and `g` needs care.
+pub const SPANISH: GraphemeRules = GraphemeRules {
+    graphemes: &[
+        ("que", "E"),
+        ("qui", "I"),
+        ("gue", "E"),
+        ("gui", "I"),
+        ("á", "A"),
+        ("é", "E"),
+        ("í", "I"),
+        ("ó", "O"),
+        ("ú", "U"),
+        ("ü", "U"),
+        ("a", "A"),
+        ("e", "E"),
+        ("i", "I"),
+        ("o", "O"),
+        ("u", "U"),
+        ("y", "I"),
+    ],
+    silent_final_e: false,
+};
+
+/// Japanese written in romaji. Long vowels spelled `ou` are a single O.
+pub const JAPANESE: GraphemeRules = GraphemeRules {
+    graphemes: &[
+        ("ou", "O"),
+        ("a", "A"),
+        ("e", "E"),
+        ("i", "I"),
+        ("o", "O"),
+        ("u", "U"),
+    ],
+    silent_final_e: false,
+};
+
+/// Returns the built-in rules for an ISO 639-1 language code.
+pub fn rules_for(language: &str) -> Option<&'static GraphemeRules> {
+    match language.to_lowercase().as_str() {
+        "en" => Some(&ENGLISH),
+        "es" => Some(&SPANISH),
+        "ja" => Some(&JAPANESE),
+        _ => None,
+    }
+}
+
+/// Converts the transcript into indices into `phonemes`, dropping vowels the profile lacks.
+pub fn expected_phonemes(
+    transcript: &str,
+    rules: &dyn VowelRules,
+    phonemes: &[String],
+) -> Vec<usize> {
+    rules
+        .vowels(transcript)
+        .into_iter()
+        .filter_map(|v| phonemes.iter().position(|p| p == v))
+        .collect()
+}
+
+/// Aligns frames to the expected phoneme sequence with dynamic time warping over their
+/// distances to each phoneme. Positions in the sequence never go backwards and every expected
+/// phoneme gets at least one voiced frame. Frames without distances are left unaligned, as are
+/// all frames when there are fewer voiced frames than expected phonemes.
+pub fn align(distances: &[Vec<f32>], expected: &[usize]) -> Vec<Option<usize>> {
+    let mut out = vec![None; distances.len()];
+    let voiced: Vec<usize> = (0..distances.len())
+        .filter(|i| {
+            let d = &distances[*i];
+            !d.is_empty() && expected.iter().all(|e| *e < d.len())
+        })
+        .collect();
+
+    let t_len = voiced.len();
+    let k_len = expected.len();
+    if k_len == 0 || t_len < k_len {
+        return out;
+    }
+
+    // Distances are compared relative to each frame's best match so loud and quiet frames
+    // weigh the same
+    let cost = |t: usize, k: usize| {
+        let d = &distances[voiced[t]];
+        let min = d.iter().fold(f32::INFINITY, |acc, v| acc.min(*v));
+        d[expected[k]] - min
+    };
+
+    let mut acc = vec![vec![f32::INFINITY; k_len]; t_len];
+    acc[0][0] = cost(0, 0);
+    for t in 1..t_len {
+        for k in 0..k_len.min(t + 1) {
+            let stay = acc[t - 1][k];
+            let advance = if k > 0 {
+                acc[t - 1][k - 1]
+            } else {
+                f32::INFINITY
+            };
+            acc[t][k] = cost(t, k) + stay.min(advance);
+        }
+    }
+
+    let mut k = k_len - 1;
+    for t in (0..t_len).rev() {
+        out[voiced[t]] = Some(k);
+        if t > 0 && k > 0 && acc[t - 1][k - 1] <= acc[t - 1][k] {
+            k -= 1;
+        }
+    }
+
+    out
+}
+
+/// Pulls every aligned keyframe's weights towards its expected phoneme by `strength`, 0
+/// keeping the analysis and 1 forcing the transcript, then picks the vowel and mouth shape
+/// again. Keyframes without a vowel or quieter than `SILENCE_AMOUNT` are left untouched.
+/// `distances` holds each keyframe's distances to the profile's phonemes, empty when the frame
+/// had none.
+pub fn constrain(
+    timeline: &mut Timeline,
+    distances: &[Vec<f32>],
+    expected: &[usize],
+    profile: &Profile,
+    strength: f32,
+) {
+    let strength = strength.clamp(0.0, 1.0);
+
+    // Silent keyframes should not use up expected phonemes
+    let voiced: Vec<Vec<f32>> = timeline
+        .keyframes
+        .iter()
+        .zip(distances.iter())
+        .map(|(k, d)| {
+            if k.vowel >= 0 && k.amount >= SILENCE_AMOUNT {
+                d.clone()
+            } else {
+                vec![]
+            }
+        })
+        .collect();
+    let alignment = align(voiced.as_slice(), expected);
+
+    for (keyframe, position) in timeline.keyframes.iter_mut().zip(alignment.iter()) {
+        let target = match position {
+            Some(k) => expected[*k],
+            None => continue,
+        };
+        if target >= keyframe.weights.len() {
+            continue;
+        }
+
+        for (i, w) in keyframe.weights.iter_mut().enumerate() {
+            let goal = if i == target { 1.0 } else { 0.0 };
+            *w += strength * (goal - *w);
+        }
+        keyframe.vowel = keyframe
+            .weights
+            .iter()
+            .enumerate()
+            .fold(
+                (0, f32::MIN),
+                |acc, (i, w)| if *w > acc.1 { (i, *w) } else { acc },
+            )
+            .0 as i32;
+        keyframe.mouth = profile.mouth_shape(keyframe.weights.as_slice(), keyframe.amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{model::MouthShape, timeline::Keyframe};
+
+    /// Distances to the 5 default phonemes, closest to `vowel`.
+    fn near(vowel: usize) -> Vec<f32> {
+        (0..5).map(|i| if i == vowel { 0.2 } else { 1.0 }).collect()
+    }
+
+    fn phonemes() -> Vec<String> {
+        Profile::default().phonemes
+    }
+
+    #[test]
+    fn reads_vowels_from_text() {
+        let phonemes = phonemes();
+
+        assert_eq!(expected_phonemes("Make it", &ENGLISH, &phonemes), [0, 2]);
+        assert_eq!(expected_phonemes("see you", &ENGLISH, &phonemes), [2, 2, 3]);
+        assert_eq!(expected_phonemes("¿Queso?", &SPANISH, &phonemes), [1, 3]);
+        assert_eq!(
+            expected_phonemes("arigatou", &JAPANESE, &phonemes),
+            [0, 2, 0, 3]
+        );
+        assert!(rules_for("EN").is_some());
+        assert!(rules_for("fr").is_none());
+    }
+
+    #[test]
+    fn aligns_frames_in_order() {
+        let distances = [near(0), near(0), near(0), near(3), near(3), near(3)];
+
+        let alignment = align(&distances, &[0, 3]);
+
+        assert_eq!(
+            alignment,
+            [Some(0), Some(0), Some(0), Some(1), Some(1), Some(1)]
+        );
+    }
+
+    #[test]
+    fn gives_every_phoneme_a_frame() {
+        let distances = [near(0), near(0), near(0)];
+
+        assert_eq!(align(&distances, &[0, 1, 2]), [Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn skips_frames_without_distances() {
+        let distances = [near(0), vec![], near(3)];
+
+        assert_eq!(align(&distances, &[0, 3]), [Some(0), None, Some(1)]);
+    }
+
+    #[test]
+    fn leaves_too_short_audio_unaligned() {
+        let distances = [near(0), vec![], near(3)];
+
+        assert_eq!(align(&distances, &[0, 3, 4]), [None, None, None]);
+        assert_eq!(align(&distances, &[]), [None, None, None]);
+    }
+
+    #[test]
+    fn constrain_skips_silence() {
+        let profile = Profile::default();
+        let keyframe = |time: f64, vowel: i32, amount: f32| {
+            let mut weights = vec![0.0; 5];
+            if vowel >= 0 {
+                weights[vowel as usize] = 1.0;
+            }
+            Keyframe {
+                time,
+                vowel,
+                amount,
+                weights,
+                mouth: MouthShape::zero(),
+            }
+        };
+        let mut timeline = Timeline::new(phonemes());
+        timeline.keyframes = vec![
+            keyframe(0.0, 0, 0.5),
+            keyframe(0.1, 0, 0.05),
+            keyframe(0.2, 0, 0.5),
+        ];
+        let distances = [near(0), near(0), near(0)];
+
+        constrain(&mut timeline, &distances, &[0, 3], &profile, 1.0);
+
+        let vowels: Vec<i32> = timeline.keyframes.iter().map(|v| v.vowel).collect();
+        assert_eq!(vowels, [0, 0, 3]);
+        assert_eq!(timeline.keyframes[2].weights, [0.0, 0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(timeline.keyframes[1].weights, [1.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,240 @@
+use std::{fs, path::Path};
+
+use crate::analysis::{
+    error::LipSyncError,
+    pcm::{self, PcmFormat},
+};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Decoded contents of a WAV file.
+#[derive(Debug, Clone)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Interleaved samples in the -1..1 range
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    pub fn duration(&self) -> f64 {
+        (self.samples.len() / self.channels) as f64 / self.sample_rate as f64
+    }
+}
+
+pub fn read(path: &Path) -> Result<Wav, LipSyncError> {
+    let bytes = fs::read(path).map_err(|e| {
+        LipSyncError::InvalidInput(format!("Unable to read {}: {}", path.display(), e))
+    })?;
+    parse(bytes.as_slice())
+}
+
+/// Parses a RIFF WAV file containing integer PCM (8/16/24/32-bit) or float (32/64-bit) data
+/// with any number of channels.
+pub fn parse(bytes: &[u8]) -> Result<Wav, LipSyncError> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("Not a RIFF WAVE file"));
+    }
+
+    let mut format: Option<(u16, usize, u32, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+
+    let mut i = 12;
+    while i + 8 <= bytes.len() {
+        let id = &bytes[i..i + 4];
+        let size =
+            u32::from_le_bytes([bytes[i + 4], bytes[i + 5], bytes[i + 6], bytes[i + 7]]) as usize;
+        let start = i + 8;
+        // Some writers put a bogus size on the data chunk when streaming, so clamp it
+        let end = (start + size).min(bytes.len());
+        let chunk = &bytes[start..end];
+
+        match id {
+            b"fmt " => {
+                if chunk.len() < 16 {
+                    return Err(invalid("fmt chunk is too small"));
+                }
+                let mut tag = u16::from_le_bytes([chunk[0], chunk[1]]);
+                let channels = u16::from_le_bytes([chunk[2], chunk[3]]) as usize;
+                let sample_rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+                let bits = u16::from_le_bytes([chunk[14], chunk[15]]);
+                if tag == FORMAT_EXTENSIBLE {
+                    if chunk.len() < 26 {
+                        return Err(invalid("Extensible fmt chunk is too small"));
+                    }
+                    // The sub format GUID starts with the actual format tag
+                    tag = u16::from_le_bytes([chunk[24], chunk[25]]);
+                }
+                format = Some((tag, channels, sample_rate, bits));
+            }
+            b"data" => data = Some(chunk),
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        i = start + size + (size & 1);
+    }
+
+    let (tag, channels, sample_rate, bits) = format.ok_or_else(|| invalid("Missing fmt chunk"))?;
+    let data = data.ok_or_else(|| invalid("Missing data chunk"))?;
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid("Invalid channel count or sample rate"));
+    }
+
+    let pcm_format = match (tag, bits) {
+        (FORMAT_PCM, 8) => PcmFormat::U8,
+        (FORMAT_PCM, 16) => PcmFormat::S16Le,
+        (FORMAT_PCM, 24) => PcmFormat::S24Le,
+        (FORMAT_PCM, 32) => PcmFormat::S32Le,
+        (FORMAT_FLOAT, 32) => PcmFormat::F32Le,
+        (FORMAT_FLOAT, 64) => {
+            let samples = data
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                .map(|v| v as f32)
+                .collect();
+            return Ok(Wav {
+                sample_rate,
+                channels,
+                samples,
+            });
+        }
+        _ => {
+            let message = format!("Unsupported format {} with {} bits per sample", tag, bits);
+            return Err(invalid(message.as_str()));
+        }
+    };
+
+    // Drop a trailing partial frame rather than failing on truncated files
+    let frame_size = pcm_format.bytes_per_sample() * channels;
+    let data = &data[..data.len() - data.len() % frame_size];
+
+    Ok(Wav {
+        sample_rate,
+        channels,
+        samples: pcm::decode(data, pcm_format, channels)?,
+    })
+}
+
+fn invalid(message: &str) -> LipSyncError {
+    LipSyncError::InvalidInput(format!("Invalid WAV file: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn fmt(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut body = vec![];
+        body.extend_from_slice(&tag.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        body.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        chunk(b"fmt ", &body)
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut out = b"RIFF".to_vec();
+        out.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+        out.extend_from_slice(&body);
+        out
+    }
+
+    #[test]
+    fn parses_16_bit_pcm() {
+        let data: Vec<u8> = [0i16, 16384, -16384, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let bytes = riff(&[fmt(FORMAT_PCM, 2, 22050, 16), chunk(b"data", &data)]);
+
+        let wav = parse(&bytes).unwrap();
+
+        assert_eq!(wav.sample_rate, 22050);
+        assert_eq!(wav.channels, 2);
+        assert_eq!(wav.samples, vec![0.0, 0.5, -0.5, 0.0]);
+        assert!((wav.duration() - 2.0 / 22050.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn parses_float() {
+        let data: Vec<u8> = [0.25f32, -1.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let bytes = riff(&[fmt(FORMAT_FLOAT, 1, 48000, 32), chunk(b"data", &data)]);
+
+        assert_eq!(parse(&bytes).unwrap().samples, vec![0.25, -1.0]);
+
+        let data: Vec<u8> = [0.25f64, -1.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let bytes = riff(&[fmt(FORMAT_FLOAT, 1, 48000, 64), chunk(b"data", &data)]);
+
+        assert_eq!(parse(&bytes).unwrap().samples, vec![0.25, -1.0]);
+    }
+
+    #[test]
+    fn reads_extensible_format_tag() {
+        let mut fmt = fmt(FORMAT_EXTENSIBLE, 1, 44100, 8);
+        let mut extension = vec![22, 0, 8, 0, 4, 0, 0, 0];
+        extension.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+        extension.extend_from_slice(&[0; 14]);
+        fmt[4..8].copy_from_slice(&(16 + extension.len() as u32).to_le_bytes());
+        fmt.extend_from_slice(&extension);
+        let bytes = riff(&[fmt, chunk(b"data", &[128, 255])]);
+
+        let wav = parse(&bytes).unwrap();
+
+        assert_eq!(wav.samples, vec![0.0, 127.0 / 128.0]);
+    }
+
+    #[test]
+    fn skips_unknown_and_odd_sized_chunks() {
+        let bytes = riff(&[
+            chunk(b"LIST", &[1, 2, 3]),
+            fmt(FORMAT_PCM, 1, 8000, 8),
+            chunk(b"data", &[0, 128, 192]),
+        ]);
+
+        assert_eq!(parse(&bytes).unwrap().samples, vec![-1.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn drops_truncated_frames() {
+        let bytes = riff(&[fmt(FORMAT_PCM, 1, 8000, 16), chunk(b"data", &[0, 64, 0])]);
+
+        assert_eq!(parse(&bytes).unwrap().samples, vec![0.5]);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let data = || chunk(b"data", &[0, 0]);
+
+        assert!(parse(b"").is_err());
+        assert!(parse(b"RIFF\0\0\0\0AVI ").is_err());
+        assert!(parse(&riff(&[data()])).is_err());
+        assert!(parse(&riff(&[fmt(FORMAT_PCM, 1, 8000, 16)])).is_err());
+        assert!(parse(&riff(&[fmt(FORMAT_PCM, 0, 8000, 16), data()])).is_err());
+        assert!(parse(&riff(&[fmt(FORMAT_PCM, 1, 8000, 12), data()])).is_err());
+        assert!(parse(&riff(&[chunk(b"fmt ", &[1, 0, 1, 0]), data()])).is_err());
+    }
+}
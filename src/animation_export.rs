@@ -0,0 +1,135 @@
+use godot::engine::{
+    animation::{TrackType, UpdateMode},
+    Animation,
+};
+use godot::prelude::*;
+
+use crate::{
+    analysis::timeline::{reduce_keys, Timeline},
+    lip_sync_timeline::LipSyncTimeline,
+};
+
+/// Turns a `LipSyncTimeline` into an `Animation` so it can be edited and played back like any
+/// other animation.
+#[derive(GodotClass)]
+#[class(base = RefCounted)]
+pub struct LipSyncAnimationExporter {
+    /// Node the tracks are applied to, relative to the `AnimationPlayer` root
+    #[export]
+    node_path: NodePath,
+    /// 0 writes blend shape tracks for a `MeshInstance3D`, 1 writes value tracks for properties
+    #[export]
+    track_mode: i32,
+    /// Blend shape or property names to drive with each phoneme's weight, in the timeline's
+    /// phoneme order. Defaults to the phoneme names when empty
+    #[export]
+    phoneme_targets: PackedStringArray,
+    /// Property receiving `mouth_open` in property mode, skipped when empty
+    #[export]
+    open_property: GString,
+    /// Property receiving `mouth_form` in property mode, skipped when empty
+    #[export]
+    form_property: GString,
+    /// Keys that can be interpolated from their neighbours within this error are dropped
+    #[export]
+    tolerance: f32,
+    #[base]
+    base: Base<RefCounted>,
+}
+
+#[godot_api]
+impl LipSyncAnimationExporter {
+    #[func]
+    pub fn export(&self, timeline: Gd<LipSyncTimeline>) -> Gd<Animation> {
+        self.to_animation(&timeline.bind().to_timeline())
+    }
+
+    pub fn to_animation(&self, timeline: &Timeline) -> Gd<Animation> {
+        let mut animation = Animation::new_gd();
+        animation.set_length(timeline.length() as f32);
+
+        let targets: Vec<String> = if self.phoneme_targets.is_empty() {
+            timeline.phonemes.clone()
+        } else {
+            self.phoneme_targets
+                .as_slice()
+                .iter()
+                .map(|v| v.to_string())
+                .collect()
+        };
+
+        for (i, target) in targets.iter().enumerate().take(timeline.phonemes.len()) {
+            let keys: Vec<(f64, f32)> = timeline
+                .keyframes
+                .iter()
+                .map(|k| (k.time, k.weights.get(i).copied().unwrap_or(0.0) * k.amount))
+                .collect();
+            self.add_track(&mut animation, target, keys.as_slice());
+        }
+
+        if self.track_mode == 1 {
+            let open: Vec<(f64, f32)> = timeline
+                .keyframes
+                .iter()
+                .map(|k| (k.time, k.mouth.open))
+                .collect();
+            let form: Vec<(f64, f32)> = timeline
+                .keyframes
+                .iter()
+                .map(|k| (k.time, k.mouth.form))
+                .collect();
+
+            if !self.open_property.is_empty() {
+                self.add_track(
+                    &mut animation,
+                    &self.open_property.to_string(),
+                    open.as_slice(),
+                );
+            }
+            if !self.form_property.is_empty() {
+                self.add_track(
+                    &mut animation,
+                    &self.form_property.to_string(),
+                    form.as_slice(),
+                );
+            }
+        }
+
+        animation
+    }
+
+    fn add_track(&self, animation: &mut Gd<Animation>, target: &str, keys: &[(f64, f32)]) {
+        let path = NodePath::from(format!("{}:{}", self.node_path, target));
+        let keys = reduce_keys(keys, self.tolerance);
+
+        if self.track_mode == 1 {
+            let idx = animation.add_track(TrackType::VALUE);
+            animation.track_set_path(idx, path);
+            animation.value_track_set_update_mode(idx, UpdateMode::CONTINUOUS);
+            for (time, value) in keys {
+                animation.track_insert_key(idx, time, Variant::from(value));
+            }
+        } else {
+            let idx = animation.add_track(TrackType::BLEND_SHAPE);
+            animation.track_set_path(idx, path);
+            for (time, value) in keys {
+                animation.blend_shape_track_insert_key(idx, time, value);
+            }
+        }
+    }
+}
+
+#[godot_api]
+impl IRefCounted for LipSyncAnimationExporter {
+    fn init(base: Base<Self::Base>) -> Self {
+        LipSyncAnimationExporter {
+            node_path: NodePath::default(),
+            track_mode: 0,
+            phoneme_targets: PackedStringArray::new(),
+            open_property: GString::new(),
+            form_property: GString::new(),
+            tolerance: 0.01,
+            base,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use godot::engine::global::Error;
+use godot::prelude::*;
+
+use crate::analysis::{
+    debug::DebugFrame,
+    error::LipSyncError,
+    model::{DataPoint, VowelEstimate},
+    settings::{CompareMethod, LipSyncSettings},
+    stats::{JobStats, STAGES},
+};
+
+/// Which keys the `updated` signal carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Every field of the estimate
+    Full,
+    /// Only `vowel`, `amount`, `mouth_open`, `mouth_form` and `start_time`
+    Compact,
+}
+
+impl From<i32> for OutputFormat {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => OutputFormat::Compact,
+            _ => OutputFormat::Full,
+        }
+    }
+}
+
+impl From<OutputFormat> for i32 {
+    fn from(v: OutputFormat) -> Self {
+        match v {
+            OutputFormat::Full => 0,
+            OutputFormat::Compact => 1,
+        }
+    }
+}
+
+pub trait ErrorCode {
+    /// Maps the error to the closest Godot error code so GDScript can check results as usual.
+    fn code(&self) -> Error;
+}
+
+impl ErrorCode for LipSyncError {
+    fn code(&self) -> Error {
+        match self {
+            LipSyncError::WorkerDied(_) => Error::ERR_UNAVAILABLE,
+            LipSyncError::InvalidInput(_) => Error::ERR_INVALID_DATA,
+            LipSyncError::Profile(_) => Error::ERR_PARSE_ERROR,
+            LipSyncError::Configuration(_) => Error::ERR_INVALID_PARAMETER,
+            LipSyncError::Panicked { .. } => Error::ERR_BUG,
+        }
+    }
+}
+
+/// Converts a `Result` into the Godot error code returned to GDScript.
+pub fn to_code(result: Result<(), LipSyncError>) -> Error {
+    match result {
+        Ok(_) => Error::OK,
+        Err(e) => e.code(),
+    }
+}
+
+impl From<&LipSyncError> for Dictionary {
+    fn from(e: &LipSyncError) -> Self {
+        let mut dict = Dictionary::new();
+
+        dict.insert("code", e.code());
+        dict.insert("kind", e.kind());
+        dict.insert("message", e.message());
+        if let LipSyncError::Panicked {
+            frame,
+            samples,
+            restarts,
+            ..
+        } = e
+        {
+            dict.insert("frame", *frame as i64);
+            dict.insert("samples", *samples as i64);
+            dict.insert("restarts", *restarts as i64);
+        }
+
+        dict
+    }
+}
+
+/// Converts an estimate into the payload of the `updated` signal.
+pub fn estimate_to_dictionary(ve: VowelEstimate, format: OutputFormat) -> Dictionary {
+    match format {
+        OutputFormat::Full => Dictionary::from(ve),
+        OutputFormat::Compact => {
+            let mut dict = Dictionary::new();
+
+            dict.insert("vowel", ve.vowel);
+            dict.insert("amount", ve.amount);
+            dict.insert("mouth_open", ve.mouth.open);
+            dict.insert("mouth_form", ve.mouth.form);
+            dict.insert("start_time", ve.start_time);
+
+            dict
+        }
+    }
+}
+
+impl From<VowelEstimate> for Dictionary {
+    fn from(ve: VowelEstimate) -> Self {
+        let mut dict = Dictionary::new();
+
+        dict.insert("estimate", ve.estimate);
+        dict.insert("vowel", ve.vowel);
+        dict.insert("amount", ve.amount);
+        dict.insert("weights", PackedFloat32Array::from(ve.weights.as_slice()));
+        dict.insert("mouth_open", ve.mouth.open);
+        dict.insert("mouth_form", ve.mouth.form);
+        dict.insert("f1", ve.f1);
+        dict.insert("f2", ve.f2);
+        dict.insert("start_time", ve.start_time);
+        dict.insert("end_time", ve.end_time);
+        dict.insert("latency", ve.latency);
+
+        dict
+    }
+}
+
+/// Overrides settings with any matching keys in `dict`, leaving the rest untouched. Fails on
+/// the first key whose value has the wrong type, in which case `settings` may be partly updated.
+pub fn merge_settings(
+    settings: &mut LipSyncSettings,
+    dict: &Dictionary,
+) -> Result<(), LipSyncError> {
+    if let Some(v) = int_setting(dict, "fft_samples")? {
+        settings.fft_samples = v.max(0) as usize;
+    }
+    if let Some(v) = float_setting(dict, "dynamic_range")? {
+        settings.dynamic_range = v;
+    }
+    if let Some(v) = float_setting(dict, "peak_threshold")? {
+        settings.peak_threshold = v;
+    }
+    if let Some(v) = int_setting(dict, "lifter_level")? {
+        settings.lifter_level = v as i32;
+    }
+    if let Some(v) = int_setting(dict, "filter_low")? {
+        settings.filter_low = v as i32;
+    }
+    if let Some(v) = int_setting(dict, "filter_high")? {
+        settings.filter_high = v as i32;
+    }
+    if let Some(v) = int_setting(dict, "compare_method")? {
+        settings.compare_method = CompareMethod::from(v as i32);
+    }
+    if let Some(v) = float_setting(dict, "attack_time")? {
+        settings.attack_time = v;
+    }
+    if let Some(v) = float_setting(dict, "release_time")? {
+        settings.release_time = v;
+    }
+
+    Ok(())
+}
+
+/// Reads an integer setting, `None` if `key` is missing.
+fn int_setting(dict: &Dictionary, key: &str) -> Result<Option<i64>, LipSyncError> {
+    match dict.get(key) {
+        Some(v) => v
+            .try_to::<i64>()
+            .map(Some)
+            .map_err(|_| LipSyncError::Configuration(format!("{} must be an int, got {}", key, v))),
+        None => Ok(None),
+    }
+}
+
+/// Reads a float setting, accepting ints as well, `None` if `key` is missing.
+fn float_setting(dict: &Dictionary, key: &str) -> Result<Option<f32>, LipSyncError> {
+    match dict.get(key) {
+        Some(v) => v
+            .try_to::<f64>()
+            .or_else(|_| v.try_to::<i64>().map(|v| v as f64))
+            .map(|v| Some(v as f32))
+            .map_err(|_| {
+                LipSyncError::Configuration(format!("{} must be a float, got {}", key, v))
+            }),
+        None => Ok(None),
+    }
+}
+
+impl From<&LipSyncSettings> for Dictionary {
+    fn from(s: &LipSyncSettings) -> Self {
+        let mut dict = Dictionary::new();
+
+        dict.insert("fft_samples", s.fft_samples as i64);
+        dict.insert("dynamic_range", s.dynamic_range);
+        dict.insert("peak_threshold", s.peak_threshold);
+        dict.insert("lifter_level", s.lifter_level);
+        dict.insert("filter_low", s.filter_low);
+        dict.insert("filter_high", s.filter_high);
+        dict.insert("compare_method", i32::from(s.compare_method));
+        dict.insert("attack_time", s.attack_time);
+        dict.insert("release_time", s.release_time);
+
+        dict
+    }
+}
+
+impl From<&JobStats> for Dictionary {
+    /// Durations are reported in microseconds, averaged over all processed frames unless
+    /// suffixed with `_last`.
+    fn from(s: &JobStats) -> Self {
+        let mut dict = Dictionary::new();
+
+        let frames = s.frames_processed;
+        dict.insert("frames_processed", frames as i64);
+        dict.insert("frames_skipped", s.frames_skipped as i64);
+        for (i, stage) in STAGES.iter().enumerate() {
+            dict.insert(format!("{}_usec", stage), s.average(s.total.0[i], frames));
+            dict.insert(
+                format!("{}_usec_last", stage),
+                s.last.0[i].as_secs_f64() * 1_000_000.0,
+            );
+        }
+        dict.insert("total_usec", s.average(s.total.total(), frames));
+        dict.insert(
+            "total_usec_last",
+            s.last.total().as_secs_f64() * 1_000_000.0,
+        );
+        dict.insert(
+            "queue_wait_usec",
+            s.average(s.queue_wait_total, s.frames_waited),
+        );
+        dict.insert(
+            "queue_wait_usec_last",
+            s.queue_wait_last.as_secs_f64() * 1_000_000.0,
+        );
+
+        dict
+    }
+}
+
+impl From<&DebugFrame> for Dictionary {
+    /// Buffers are returned as `PackedFloat32Array`s, peaks split into positions in bins and
+    /// amplitudes.
+    fn from(d: &DebugFrame) -> Self {
+        let mut dict = Dictionary::new();
+        let positions = |peaks: &[DataPoint]| -> PackedFloat32Array {
+            PackedFloat32Array::from(peaks.iter().map(|v| v.0).collect::<Vec<_>>().as_slice())
+        };
+        let amplitudes = |peaks: &[DataPoint]| -> PackedFloat32Array {
+            PackedFloat32Array::from(peaks.iter().map(|v| v.1).collect::<Vec<_>>().as_slice())
+        };
+
+        dict.insert("windowed", PackedFloat32Array::from(d.windowed.as_slice()));
+        dict.insert(
+            "magnitude",
+            PackedFloat32Array::from(d.magnitude.as_slice()),
+        );
+        dict.insert(
+            "log_spectrum",
+            PackedFloat32Array::from(d.log_spectrum.as_slice()),
+        );
+        dict.insert("envelope", PackedFloat32Array::from(d.envelope.as_slice()));
+        dict.insert("peak_positions", positions(d.peaks.as_slice()));
+        dict.insert("peak_amplitudes", amplitudes(d.peaks.as_slice()));
+        dict.insert(
+            "averaged_peak_positions",
+            positions(d.averaged_peaks.as_slice()),
+        );
+        dict.insert(
+            "averaged_peak_amplitudes",
+            amplitudes(d.averaged_peaks.as_slice()),
+        );
+        dict.insert(
+            "distances",
+            PackedFloat32Array::from(d.distances.as_slice()),
+        );
+        dict.insert("estimate", d.estimate);
+        dict.insert("vowel", d.vowel);
+        dict.insert("amount", d.amount);
+
+        dict
+    }
+}
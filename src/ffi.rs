@@ -0,0 +1,597 @@
+//! C API over `Analyzer`, for engines other than Godot. `include/lip_sync.h` is generated from
+//! this file with cbindgen, see the README.
+//!
+//! A handle must only be used by one thread at a time. Strings and buffers returned through a
+//! handle stay valid until the next call on that handle.
+
+use std::{
+    collections::VecDeque,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::analysis::{
+    error::LipSyncError,
+    model::{Profile, VowelEstimate},
+    pool, profile,
+    settings::{CompareMethod, LipSyncSettings},
+    Analyzer,
+};
+
+/// Outcome of a call, negative values being errors.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LipSyncStatus {
+    Ok = 0,
+    /// No result is ready yet
+    Empty = 1,
+    WorkerDied = -1,
+    InvalidInput = -2,
+    Profile = -3,
+    Configuration = -4,
+    /// Analysis panicked on a frame, the stream keeps running
+    Panicked = -5,
+    NullPointer = -6,
+}
+
+impl From<&LipSyncError> for LipSyncStatus {
+    fn from(e: &LipSyncError) -> Self {
+        match e {
+            LipSyncError::WorkerDied(_) => LipSyncStatus::WorkerDied,
+            LipSyncError::InvalidInput(_) => LipSyncStatus::InvalidInput,
+            LipSyncError::Profile(_) => LipSyncStatus::Profile,
+            LipSyncError::Configuration(_) => LipSyncStatus::Configuration,
+            LipSyncError::Panicked { .. } => LipSyncStatus::Panicked,
+        }
+    }
+}
+
+/// Analysis settings, see `lip_sync_default_config` for the defaults.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LipSyncConfig {
+    /// Samples used per analysis, a power of 2 from 64 to 8192
+    pub fft_samples: u32,
+    /// Loudness range in dB mapped to an amount of 0 to 1
+    pub dynamic_range: f32,
+    /// Minimum height of a spectral envelope peak, relative to the highest point
+    pub peak_threshold: f32,
+    /// Number of cepstral coefficients kept when smoothing the spectrum
+    pub lifter_level: i32,
+    /// Values at or below this are cut from the spectrum, at most `fft_samples / 2`
+    pub filter_low: i32,
+    /// Values at or above this are cut from the spectrum, at most `fft_samples / 2`
+    pub filter_high: i32,
+    /// 0 L1, 1 L2, 2 cosine
+    pub compare_method: i32,
+    /// Seconds for the output to follow a rising amount, 0 to follow immediately
+    pub attack_time: f32,
+    /// Seconds for the output to follow a falling amount, 0 to follow immediately
+    pub release_time: f32,
+}
+
+impl From<&LipSyncSettings> for LipSyncConfig {
+    fn from(settings: &LipSyncSettings) -> Self {
+        LipSyncConfig {
+            fft_samples: settings.fft_samples as u32,
+            dynamic_range: settings.dynamic_range,
+            peak_threshold: settings.peak_threshold,
+            lifter_level: settings.lifter_level,
+            filter_low: settings.filter_low,
+            filter_high: settings.filter_high,
+            compare_method: settings.compare_method.into(),
+            attack_time: settings.attack_time,
+            release_time: settings.release_time,
+        }
+    }
+}
+
+impl From<&LipSyncConfig> for LipSyncSettings {
+    fn from(config: &LipSyncConfig) -> Self {
+        LipSyncSettings {
+            fft_samples: config.fft_samples as usize,
+            dynamic_range: config.dynamic_range,
+            peak_threshold: config.peak_threshold,
+            lifter_level: config.lifter_level,
+            filter_low: config.filter_low,
+            filter_high: config.filter_high,
+            compare_method: CompareMethod::from(config.compare_method),
+            attack_time: config.attack_time,
+            release_time: config.release_time,
+        }
+    }
+}
+
+/// Analysis of one frame. Per-phoneme weights are read with `lip_sync_last_weights`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LipSyncResult {
+    /// Closest phoneme before smoothing, -1 if none
+    pub estimate: i32,
+    /// Phoneme after smoothing, -1 if none
+    pub vowel: i32,
+    pub amount: f32,
+    /// 0 closed to 1 fully open
+    pub mouth_open: f32,
+    /// -1 pouted to 1 smiling
+    pub mouth_form: f32,
+    /// First and second formant positions as FFT bins, 0 if no peaks were found
+    pub f1: f32,
+    pub f2: f32,
+    /// Time span of the analyzed frame, in seconds
+    pub start_time: f64,
+    pub end_time: f64,
+    /// Seconds between the frame being pushed and the result being ready
+    pub latency: f64,
+}
+
+impl From<&VowelEstimate> for LipSyncResult {
+    fn from(ve: &VowelEstimate) -> Self {
+        LipSyncResult {
+            estimate: ve.estimate,
+            vowel: ve.vowel,
+            amount: ve.amount,
+            mouth_open: ve.mouth.open,
+            mouth_form: ve.mouth.form,
+            f1: ve.f1,
+            f2: ve.f2,
+            start_time: ve.start_time,
+            end_time: ve.end_time,
+            latency: ve.latency,
+        }
+    }
+}
+
+/// Opaque stream created by `lip_sync_create`.
+pub struct LipSyncHandle {
+    analyzer: Analyzer,
+    /// Results taken from the analyzer but not pulled yet
+    pending: VecDeque<Result<VowelEstimate, LipSyncError>>,
+    last_weights: Vec<f32>,
+    last_error: CString,
+}
+
+impl LipSyncHandle {
+    fn fail(&mut self, e: &LipSyncError) -> LipSyncStatus {
+        // Messages never contain interior nul bytes, but don't trust it across the boundary
+        self.last_error = CString::new(e.to_string().replace('\0', " ")).unwrap_or_default();
+        LipSyncStatus::from(e)
+    }
+
+    fn check(&mut self, result: Result<(), LipSyncError>) -> LipSyncStatus {
+        match result {
+            Ok(_) => LipSyncStatus::Ok,
+            Err(e) => self.fail(&e),
+        }
+    }
+}
+
+/// Runs `f` on the handle, turning panics into `Panicked` since they must not unwind into C.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed.
+unsafe fn with_handle<F>(handle: *mut LipSyncHandle, f: F) -> LipSyncStatus
+where
+    F: FnOnce(&mut LipSyncHandle) -> LipSyncStatus,
+{
+    // SAFETY: the caller passes a handle from `lip_sync_create` that is not used elsewhere
+    let handle = match unsafe { handle.as_mut() } {
+        Some(v) => v,
+        None => return LipSyncStatus::NullPointer,
+    };
+
+    panic::catch_unwind(AssertUnwindSafe(|| f(handle))).unwrap_or(LipSyncStatus::Panicked)
+}
+
+/// Fills `config` with the default settings.
+///
+/// # Safety
+///
+/// `config` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_default_config(config: *mut LipSyncConfig) -> LipSyncStatus {
+    // SAFETY: the caller passes a writable config or null
+    match unsafe { config.as_mut() } {
+        Some(v) => {
+            *v = LipSyncConfig::from(&LipSyncSettings::default());
+            LipSyncStatus::Ok
+        }
+        None => LipSyncStatus::NullPointer,
+    }
+}
+
+/// Creates a stream analyzing audio at `sample_rate` with the default settings, on a shared
+/// worker pool when `threaded` or otherwise as samples are pushed. Returns null on failure.
+#[no_mangle]
+pub extern "C" fn lip_sync_create(threaded: bool, sample_rate: f64) -> *mut LipSyncHandle {
+    let created = panic::catch_unwind(|| {
+        let mut analyzer = Analyzer::new(sample_rate);
+        analyzer.set_sample_rate(sample_rate).ok()?;
+        analyzer.start(threaded).ok()?;
+
+        Some(LipSyncHandle {
+            analyzer,
+            pending: VecDeque::new(),
+            last_weights: vec![],
+            last_error: CString::default(),
+        })
+    });
+
+    match created {
+        Ok(Some(v)) => Box::into_raw(Box::new(v)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Shuts the stream down and frees the handle. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_destroy(handle: *mut LipSyncHandle) {
+    if handle.is_null() {
+        return;
+    }
+
+    // SAFETY: the handle comes from `lip_sync_create` and is not used after this call
+    let mut handle = unsafe { Box::from_raw(handle) };
+    handle.analyzer.stop().ok();
+}
+
+/// Stops the shared worker pool and waits for its threads to exit. Call it after destroying
+/// every handle and before unloading the library, since the workers run its code. Handles
+/// created afterwards start a new pool.
+#[no_mangle]
+pub extern "C" fn lip_sync_shutdown() {
+    panic::catch_unwind(pool::shutdown).ok();
+}
+
+/// Applies new settings between frames. The previous settings are kept if they are invalid.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed. `config` must
+/// be null or readable.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_configure(
+    handle: *mut LipSyncHandle,
+    config: *const LipSyncConfig,
+) -> LipSyncStatus {
+    with_handle(handle, |handle| {
+        // SAFETY: the caller passes a readable config or null
+        match unsafe { config.as_ref() } {
+            Some(v) => {
+                let result = handle.analyzer.configure(LipSyncSettings::from(v));
+                handle.check(result)
+            }
+            None => LipSyncStatus::NullPointer,
+        }
+    })
+}
+
+/// Replaces the phoneme templates with a nul-terminated JSON profile, in the layout written by
+/// the Godot node's `get_profile_json`.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed. `json` must be
+/// null or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_load_profile(
+    handle: *mut LipSyncHandle,
+    json: *const c_char,
+) -> LipSyncStatus {
+    with_handle(handle, |handle| {
+        if json.is_null() {
+            return LipSyncStatus::NullPointer;
+        }
+
+        // SAFETY: the caller passes a nul-terminated string
+        let text = unsafe { CStr::from_ptr(json) }.to_string_lossy();
+        let result = profile::parse(&text).and_then(|v| handle.analyzer.set_profile(v));
+        handle.check(result)
+    })
+}
+
+/// Queues `len` mono samples starting at `start_time` seconds, or right after the previously
+/// pushed samples when negative. At least `fft_samples` samples are needed per call.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed. `samples` must
+/// be null or hold `len` readable values.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_push_samples(
+    handle: *mut LipSyncHandle,
+    samples: *const f32,
+    len: usize,
+    start_time: f64,
+) -> LipSyncStatus {
+    with_handle(handle, |handle| {
+        if samples.is_null() {
+            return LipSyncStatus::NullPointer;
+        }
+
+        // SAFETY: the caller passes `len` readable samples
+        let data = unsafe { slice::from_raw_parts(samples, len) }.to_vec();
+        let start_time = if start_time < 0.0 {
+            None
+        } else {
+            Some(start_time)
+        };
+        let result = handle.analyzer.push(data, start_time);
+        handle.check(result)
+    })
+}
+
+/// Takes the oldest result into `result`, returning `Empty` when none is ready. Frames that
+/// failed are reported in order with their error status, see `lip_sync_last_error`.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed. `result` must
+/// be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_pull_result(
+    handle: *mut LipSyncHandle,
+    result: *mut LipSyncResult,
+) -> LipSyncStatus {
+    with_handle(handle, |handle| {
+        if result.is_null() {
+            return LipSyncStatus::NullPointer;
+        }
+
+        if handle.pending.is_empty() {
+            let results = handle.analyzer.poll();
+            handle.pending.extend(results);
+        }
+
+        match handle.pending.pop_front() {
+            Some(Ok(v)) => {
+                // SAFETY: the caller passes a writable result
+                unsafe { *result = LipSyncResult::from(&v) };
+                handle.last_weights = v.weights;
+                LipSyncStatus::Ok
+            }
+            Some(Err(e)) => handle.fail(&e),
+            None => LipSyncStatus::Empty,
+        }
+    })
+}
+
+/// Number of phonemes in the profile, which is also the length of the weights.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_phoneme_count(handle: *const LipSyncHandle) -> usize {
+    // SAFETY: the caller passes a handle from `lip_sync_create` or null
+    match unsafe { handle.as_ref() } {
+        Some(v) => v
+            .analyzer
+            .profile()
+            .map_or_else(|| Profile::default().phonemes.len(), |p| p.phonemes.len()),
+        None => 0,
+    }
+}
+
+/// Copies up to `len` per-phoneme weights of the last pulled result into `weights`. Returns how
+/// many were copied.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed. `weights` must
+/// be null or have room for `len` values.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_last_weights(
+    handle: *const LipSyncHandle,
+    weights: *mut f32,
+    len: usize,
+) -> usize {
+    // SAFETY: the caller passes a handle from `lip_sync_create` or null
+    let handle = match unsafe { handle.as_ref() } {
+        Some(v) => v,
+        None => return 0,
+    };
+    if weights.is_null() {
+        return 0;
+    }
+
+    let count = len.min(handle.last_weights.len());
+    // SAFETY: the caller passes room for `len` weights
+    unsafe { ptr::copy_nonoverlapping(handle.last_weights.as_ptr(), weights, count) };
+
+    count
+}
+
+/// Message of the last error returned through the handle, empty if there was none.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `lip_sync_create` and not be destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn lip_sync_last_error(handle: *const LipSyncHandle) -> *const c_char {
+    // SAFETY: the caller passes a handle from `lip_sync_create` or null
+    match unsafe { handle.as_ref() } {
+        Some(v) => v.last_error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * 700.0 * i as f32 / 44100.0).sin())
+            .collect()
+    }
+
+    fn last_error(handle: *const LipSyncHandle) -> String {
+        // SAFETY: the handle is live and its message is nul-terminated
+        unsafe { CStr::from_ptr(lip_sync_last_error(handle)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn null_pointers_are_reported() {
+        let mut config = LipSyncConfig::from(&LipSyncSettings::default());
+        let mut result = LipSyncResult::default();
+        let mut weights = [0.0; 4];
+        let handle = lip_sync_create(false, 44100.0);
+
+        // SAFETY: every pointer is null, live or points to a local
+        unsafe {
+            assert_eq!(
+                lip_sync_default_config(ptr::null_mut()),
+                LipSyncStatus::NullPointer
+            );
+            assert_eq!(
+                lip_sync_configure(ptr::null_mut(), &config),
+                LipSyncStatus::NullPointer
+            );
+            assert_eq!(
+                lip_sync_configure(handle, ptr::null()),
+                LipSyncStatus::NullPointer
+            );
+            assert_eq!(
+                lip_sync_load_profile(handle, ptr::null()),
+                LipSyncStatus::NullPointer
+            );
+            assert_eq!(
+                lip_sync_push_samples(handle, ptr::null(), 1024, -1.0),
+                LipSyncStatus::NullPointer
+            );
+            assert_eq!(
+                lip_sync_pull_result(ptr::null_mut(), &mut result),
+                LipSyncStatus::NullPointer
+            );
+            assert_eq!(
+                lip_sync_pull_result(handle, ptr::null_mut()),
+                LipSyncStatus::NullPointer
+            );
+            assert_eq!(lip_sync_phoneme_count(ptr::null()), 0);
+            assert_eq!(
+                lip_sync_last_weights(ptr::null(), weights.as_mut_ptr(), 4),
+                0
+            );
+            assert_eq!(lip_sync_last_weights(handle, ptr::null_mut(), 4), 0);
+            assert!(lip_sync_last_error(ptr::null()).is_null());
+            assert_eq!(lip_sync_default_config(&mut config), LipSyncStatus::Ok);
+
+            lip_sync_destroy(ptr::null_mut());
+            lip_sync_destroy(handle);
+        }
+    }
+
+    #[test]
+    fn create_rejects_invalid_sample_rate() {
+        assert!(lip_sync_create(false, 0.0).is_null());
+    }
+
+    #[test]
+    fn push_and_pull_inline() {
+        let handle = lip_sync_create(false, 44100.0);
+        let samples = voice(1024);
+        let mut result = LipSyncResult::default();
+
+        // SAFETY: the handle is live and the buffers are locals
+        unsafe {
+            assert_eq!(
+                lip_sync_push_samples(handle, samples.as_ptr(), samples.len(), 0.5),
+                LipSyncStatus::Ok
+            );
+            assert_eq!(lip_sync_pull_result(handle, &mut result), LipSyncStatus::Ok);
+            assert_eq!(result.start_time, 0.5);
+            assert_eq!(
+                lip_sync_pull_result(handle, &mut result),
+                LipSyncStatus::Empty
+            );
+
+            let count = lip_sync_phoneme_count(handle);
+            let mut weights = vec![0.0; count + 2];
+            assert_eq!(
+                lip_sync_last_weights(handle, weights.as_mut_ptr(), weights.len()),
+                count
+            );
+
+            lip_sync_destroy(handle);
+        }
+    }
+
+    #[test]
+    fn errors_set_status_and_message() {
+        let handle = lip_sync_create(false, 44100.0);
+        let samples = voice(100);
+
+        // SAFETY: the handle is live and the buffers are locals
+        unsafe {
+            assert!(last_error(handle).is_empty());
+            assert_eq!(
+                lip_sync_push_samples(handle, samples.as_ptr(), samples.len(), -1.0),
+                LipSyncStatus::InvalidInput
+            );
+            assert!(last_error(handle).contains("1024"));
+
+            let mut config = LipSyncConfig::from(&LipSyncSettings::default());
+            config.compare_method = 7;
+            assert_eq!(
+                lip_sync_configure(handle, &config),
+                LipSyncStatus::Configuration
+            );
+            assert!(last_error(handle).contains("compare_method"));
+
+            let json = CString::new("{}").unwrap();
+            assert_eq!(
+                lip_sync_load_profile(handle, json.as_ptr()),
+                LipSyncStatus::Profile
+            );
+
+            lip_sync_destroy(handle);
+        }
+    }
+
+    #[test]
+    fn config_round_trips_settings() {
+        let settings = LipSyncSettings {
+            fft_samples: 512,
+            compare_method: CompareMethod::Cosine,
+            attack_time: 0.05,
+            ..LipSyncSettings::default()
+        };
+
+        let config = LipSyncConfig::from(&settings);
+
+        assert_eq!(config.compare_method, 2);
+        assert_eq!(LipSyncSettings::from(&config), settings);
+    }
+
+    #[test]
+    fn load_profile_changes_phoneme_count() {
+        let handle = lip_sync_create(false, 44100.0);
+        let mut profile = Profile::default();
+        profile.phonemes.truncate(2);
+        profile.peaks3.truncate(2);
+        profile.peaks4.truncate(2);
+        profile.mouth_shapes.truncate(2);
+        let json = CString::new(profile::to_json(&profile)).unwrap();
+
+        // SAFETY: the handle is live and the string is nul-terminated
+        unsafe {
+            assert_eq!(lip_sync_phoneme_count(handle), 5);
+            assert_eq!(
+                lip_sync_load_profile(handle, json.as_ptr()),
+                LipSyncStatus::Ok
+            );
+            assert_eq!(lip_sync_phoneme_count(handle), 2);
+
+            lip_sync_destroy(handle);
+        }
+    }
+}
@@ -1,13 +1,34 @@
 use godot::prelude::*;
 
 mod lip_sync;
+mod lip_sync_audio_effect;
+mod lip_sync_calibration;
+mod lip_sync_profile;
+mod lip_sync_sprite;
+mod lip_sync_timeline;
+mod lip_sync_timeline_player;
 
-mod algorithm;
-mod debug;
-mod job;
-mod model;
+mod analysis;
+mod animation_export;
+mod convert;
+mod ffi;
 
 struct LipSyncLib;
 
 #[gdextension]
-unsafe impl ExtensionLibrary for LipSyncLib {}
+unsafe impl ExtensionLibrary for LipSyncLib {
+    fn on_level_init(level: InitLevel) {
+        if level == InitLevel::Scene {
+            analysis::log::set_logger(Some(|message| godot_print!("{}", message)));
+            lip_sync_profile::register_loader();
+        }
+    }
+
+    fn on_level_deinit(level: InitLevel) {
+        if level == InitLevel::Scene {
+            lip_sync_profile::unregister_loader();
+            analysis::pool::shutdown();
+            analysis::log::set_logger(None);
+        }
+    }
+}
@@ -1,25 +1,194 @@
 use godot::prelude::*;
-use lazy_static::lazy_static;
-use rand::{rngs::ThreadRng, Rng};
-use std::{
-    collections::{HashMap, VecDeque},
-    ops::{Add, Div, Index, Mul, MulAssign},
-    sync::mpsc,
-    sync::{Arc, Mutex},
-    thread,
-};
 
-use crate::{job, job::JobMessage};
+use crate::{
+    analysis::{
+        bake,
+        error::LipSyncError,
+        events::{self, EventDetector, EventSettings, LipSyncEvent},
+        input::{downmix, ChannelMode},
+        model::{Profile, VowelEstimate},
+        pcm::{self, PcmFormat},
+        pool::OverflowPolicy,
+        profile,
+        settings::{CompareMethod, LipSyncSettings},
+        tap,
+        transcript::{self, Transcript, DEFAULT_TRANSCRIPT_STRENGTH},
+        wav::{self, Wav},
+        Analyzer,
+    },
+    convert::{estimate_to_dictionary, merge_settings, to_code, ErrorCode, OutputFormat},
+    lip_sync_profile::LipSyncProfile,
+    lip_sync_timeline::LipSyncTimeline,
+};
+use godot::engine::{
+    global::Error, object::ConnectFlags, AudioServer, FileAccess, Performance, Resource,
+};
 
 const LIP_SYNC_UPDATED: &str = "updated";
-const LIP_SYNC_PANICKED: &str = "panicked";
+const LIP_SYNC_ERROR: &str = "error";
+const LIP_SYNC_PHONEME_CHANGED: &str = "phoneme_changed";
+const LIP_SYNC_SPEECH_STARTED: &str = "speech_started";
+const LIP_SYNC_SPEECH_ENDED: &str = "speech_ended";
+const LIP_SYNC_LEVEL_PEAKED: &str = "level_peaked";
+const GET_STAT: &str = "get_stat";
+const RESOURCE_CHANGED: &str = "changed";
+const ON_PROFILE_CHANGED: &str = "on_profile_changed";
+
+/// Stats registered as `Performance` custom monitors.
+const MONITORED_STATS: [&str; 5] = [
+    "frames_processed",
+    "frames_skipped",
+    "total_usec",
+    "queue_wait_usec",
+    "queue_depth",
+];
 
 #[derive(GodotClass)]
 #[class(base = Node)]
 pub struct LipSyncRs {
-    join_handle: Option<thread::JoinHandle<()>>,
-    sender: mpsc::Sender<job::JobMessage>,
-    receiver: mpsc::Receiver<job::JobMessage>,
+    /// Analyzes frames on the shared worker pool when true, or immediately on the calling
+    /// thread when false
+    #[export_group(name = "Processing")]
+    #[export]
+    #[var(get, set = set_threaded)]
+    threaded: bool,
+    /// Registers stats as `Performance` custom monitors while the node is in the tree
+    #[export]
+    #[var(get, set = set_performance_monitors)]
+    performance_monitors: bool,
+    /// Captures the intermediate buffers of every frame for `get_debug_frame`, which costs a
+    /// few copies per frame
+    #[export]
+    #[var(get, set = set_debug_capture)]
+    debug_capture: bool,
+    /// Calls `poll` every frame from `_process`
+    #[export]
+    #[var]
+    auto_poll: bool,
+    /// Makes `poll` emit `updated` only for the latest result when several are waiting, with
+    /// the number of skipped results as `coalesced`
+    #[export]
+    #[var]
+    coalesce_results: bool,
+    /// Results skipped by `coalesce_results` so far
+    coalesced_results: u64,
+    registered_monitors: Vec<StringName>,
+    analyzer: Analyzer,
+    /// Error raised before the node could emit signals, emitted once ready
+    pending_error: Option<LipSyncError>,
+
+    /// Used to turn sample counts into timestamps
+    #[export_group(name = "Input")]
+    #[export(range = (8000.0, 192000.0, or_greater))]
+    #[var(get = get_sample_rate, set = set_sample_rate)]
+    sample_rate: f64,
+    /// Channel analyzed by `update_stereo`, `update_packed` and bus taps: 0 left, 1 right, 2 the
+    /// average of all channels, 3 whichever channel is loudest in the buffer
+    #[export(enum = (Left, Right, Mid, MaxEnergy))]
+    #[var(get, set = set_channel_mode)]
+    channel_mode: i32,
+    /// Name of the `LipSyncAudioEffect` tap to analyze, empty to only analyze audio passed to
+    /// the `update` methods
+    #[export]
+    #[var(get, set = set_bus_tap)]
+    bus_tap: GString,
+
+    #[export_group(name = "Analysis")]
+    #[export(enum = (
+        Samples64 = 64,
+        Samples128 = 128,
+        Samples256 = 256,
+        Samples512 = 512,
+        Samples1024 = 1024,
+        Samples2048 = 2048,
+        Samples4096 = 4096,
+        Samples8192 = 8192
+    ))]
+    #[var(get, set = set_fft_samples)]
+    fft_samples: i64,
+    #[export(range = (1.0, 200.0, or_greater))]
+    #[var(get, set = set_dynamic_range)]
+    dynamic_range: f32,
+    #[export(range = (0.0, 1.0, 0.01))]
+    #[var(get, set = set_peak_threshold)]
+    peak_threshold: f32,
+    #[export(range = (1, 255))]
+    #[var(get, set = set_lifter_level)]
+    lifter_level: i32,
+    #[export(range = (0, 255))]
+    #[var(get, set = set_filter_low)]
+    filter_low: i32,
+    #[export(range = (0, 255))]
+    #[var(get, set = set_filter_high)]
+    filter_high: i32,
+    /// How peaks are compared against the profile: 0 L1, 1 L2, 2 cosine
+    #[export(enum = (L1, L2, Cosine))]
+    #[var(get, set = set_compare_method)]
+    compare_method: i32,
+
+    /// Seconds for the output to follow a rising amount, 0 to follow immediately
+    #[export_group(name = "Smoothing")]
+    #[export(range = (0.0, 1.0, 0.005, or_greater))]
+    #[var(get, set = set_attack_time)]
+    attack_time: f32,
+    /// Seconds for the output to follow a falling amount, 0 to follow immediately
+    #[export(range = (0.0, 1.0, 0.005, or_greater))]
+    #[var(get, set = set_release_time)]
+    release_time: f32,
+
+    /// Phoneme templates and mouth shapes to use, takes precedence over `profile_path`
+    #[export_group(name = "Profile")]
+    #[export]
+    #[var(get, set = set_profile)]
+    profile: Option<Gd<LipSyncProfile>>,
+    /// JSON file with the phoneme templates and mouth shapes, used when `profile` is not set.
+    /// Empty for the built-in profile
+    #[export(file = "*.json,*.lsprofile")]
+    #[var(get, set = set_profile_path)]
+    profile_path: GString,
+
+    /// Keys carried by `updated`: 0 every field, 1 only `vowel`, `amount`, `mouth_open`,
+    /// `mouth_form` and `start_time`
+    #[export_group(name = "Output")]
+    #[export(enum = (Full, Compact))]
+    #[var]
+    output_format: i32,
+
+    /// `amount` above which results count as speech
+    #[export_group(name = "Events")]
+    #[export(range = (0.0, 1.0, 0.01))]
+    #[var]
+    speech_threshold: f32,
+    /// Seconds `amount` must stay above `speech_threshold` before `speech_started`
+    #[export(range = (0.0, 1.0, 0.01, or_greater))]
+    #[var]
+    speech_start_delay: f64,
+    /// Seconds `amount` must stay below `speech_threshold` before `speech_ended`, so short
+    /// pauses between words are ignored
+    #[export(range = (0.0, 2.0, 0.01, or_greater))]
+    #[var]
+    speech_end_delay: f64,
+    /// Seconds a new phoneme must be held before `phoneme_changed`
+    #[export(range = (0.0, 0.5, 0.005, or_greater))]
+    #[var]
+    phoneme_hold: f64,
+    /// `amount` a peak must reach to emit `level_peaked`
+    #[export(range = (0.0, 1.0, 0.01))]
+    #[var]
+    level_peak_threshold: f32,
+    events: EventDetector,
+
+    /// Seconds between keyframes when baking
+    #[export_group(name = "Baking")]
+    #[export(range = (0.001, 0.1, 0.001, or_greater))]
+    #[var]
+    bake_hop: f64,
+    /// How strongly `bake_file_with_transcript` pulls keyframes towards the transcript, 0
+    /// keeping the analysis and 1 forcing the transcript's vowels
+    #[export(range = (0.0, 1.0, 0.01))]
+    #[var]
+    transcript_strength: f32,
+
     #[base]
     base: Base<Node>,
 }
@@ -33,65 +202,751 @@ impl LipSyncRs {
     #[signal]
     fn updated();
 
+    /// Emitted with a `Dictionary` containing the `code`, `kind` and `message` of the error.
+    #[signal]
+    fn error();
+
+    /// Emitted with the previous and new phoneme index once a phoneme has been held for
+    /// `phoneme_hold` seconds while speaking, and with a new index of -1 when speech ends.
+    #[signal]
+    fn phoneme_changed(old: i64, new: i64);
+
     #[signal]
-    fn panicked();
+    fn speech_started();
 
+    /// Emitted with the length of the speech in seconds, not counting `speech_end_delay`.
+    #[signal]
+    fn speech_ended(duration: f64);
+
+    /// Emitted with the highest `amount` reached each time it rises above
+    /// `level_peak_threshold`.
+    #[signal]
+    fn level_peaked(amount: f32);
+
+    /// Queues mono samples, timestamped right after the previously queued samples.
     #[func]
-    pub fn update(&mut self, stream: Array<f32>) {
-        self.sender
-            .send(JobMessage::InputData(stream))
-            .expect("Unable to send stream to thread");
+    pub fn update(&mut self, stream: Array<f32>) -> Error {
+        let data: Vec<f32> = stream.iter_shared().collect();
+        to_code(self.send_samples(data, None))
     }
 
+    /// Queues mono samples starting at `timestamp` seconds, for example from
+    /// `AudioStreamPlayer.get_playback_position`.
     #[func]
-    pub fn poll(&mut self) {
-        match self.receiver.try_recv() {
-            Ok(v) => match v {
-                JobMessage::OutputData(od) => {
-                    // godot_print!("Emitted signal: {:?}", LIP_SYNC_UPDATED);
+    pub fn update_at(&mut self, stream: Array<f32>, timestamp: f64) -> Error {
+        let data: Vec<f32> = stream.iter_shared().collect();
+        to_code(self.send_samples(data, Some(timestamp)))
+    }
 
-                    self.base.emit_signal(
-                        LIP_SYNC_UPDATED.into(),
-                        &[Variant::from(Dictionary::from(od))],
-                    );
-                }
-                _ => {
-                    // Unexpected data
-                    self.sender.send(JobMessage::Shutdown).expect("When shutting down thread because of invalid message, encoutered error. Shutting down anyways.");
-                }
-            },
+    /// Queues mono samples starting at `sample_position` in the audio stream.
+    #[func]
+    pub fn update_at_sample(&mut self, stream: Array<f32>, sample_position: i64) -> Error {
+        self.analyzer
+            .set_sample_position(sample_position.max(0) as u64);
+        self.update(stream)
+    }
+
+    /// Queues stereo frames, as returned by `AudioEffectCapture.get_buffer`.
+    #[func]
+    pub fn update_stereo(&mut self, frames: PackedVector2Array) -> Error {
+        let interleaved: Vec<f32> = frames.as_slice().iter().flat_map(|v| [v.x, v.y]).collect();
+        let data = downmix(
+            interleaved.as_slice(),
+            2,
+            ChannelMode::from(self.channel_mode),
+        );
+        to_code(self.send_samples(data, None))
+    }
+
+    /// Queues samples interleaved over `channels` channels, 1 meaning mono.
+    #[func]
+    pub fn update_packed(&mut self, samples: PackedFloat32Array, channels: i64) -> Error {
+        if channels < 1 {
+            return LipSyncError::InvalidInput(format!("Invalid channel count {}", channels))
+                .code();
+        }
+
+        let data = downmix(
+            samples.as_slice(),
+            channels as usize,
+            ChannelMode::from(self.channel_mode),
+        );
+        to_code(self.send_samples(data, None))
+    }
+
+    /// Queues raw PCM bytes, for example from network voice chat packets. `format` is one of
+    /// 0 unsigned 8-bit, 1/2 signed 16-bit little/big endian, 3/4 signed 24-bit little/big
+    /// endian, 5/6 signed 32-bit little/big endian, 7/8 32-bit float little/big endian.
+    #[func]
+    pub fn update_pcm(&mut self, bytes: PackedByteArray, format: i32, channels: i64) -> Error {
+        to_code(self.send_pcm(bytes.as_slice(), format, channels))
+    }
+
+    #[func]
+    pub fn get_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    #[func]
+    pub fn set_sample_rate(&mut self, sample_rate: f64) -> Error {
+        let result = self.analyzer.set_sample_rate(sample_rate);
+        if result.is_ok() {
+            self.sample_rate = sample_rate;
+        }
+        to_code(result)
+    }
+
+    /// Applies every setting found in `settings` at once, see `LipSyncSettings` for the keys.
+    /// Nothing is changed if a value has the wrong type or the resulting settings are invalid.
+    #[func]
+    pub fn configure(&mut self, settings: Dictionary) -> Error {
+        let mut new_settings = self.settings();
+        let result = merge_settings(&mut new_settings, &settings)
+            .and_then(|_| self.apply_settings(new_settings));
+        to_code(result)
+    }
+
+    #[func]
+    pub fn get_settings(&self) -> Dictionary {
+        Dictionary::from(&self.settings())
+    }
+
+    #[func]
+    pub fn set_fft_samples(&mut self, value: i64) {
+        self.fft_samples = value.max(0);
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_dynamic_range(&mut self, value: f32) {
+        self.dynamic_range = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_peak_threshold(&mut self, value: f32) {
+        self.peak_threshold = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_lifter_level(&mut self, value: i32) {
+        self.lifter_level = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_filter_low(&mut self, value: i32) {
+        self.filter_low = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_filter_high(&mut self, value: i32) {
+        self.filter_high = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_compare_method(&mut self, value: i32) {
+        self.compare_method = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_attack_time(&mut self, value: f32) {
+        self.attack_time = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    #[func]
+    pub fn set_release_time(&mut self, value: f32) {
+        self.release_time = value;
+        self.apply_or_emit(self.settings());
+    }
+
+    /// Uses the templates of a `LipSyncProfile` resource, or falls back to `profile_path` when
+    /// null. The current profile is kept and `error` is emitted if the resource is incomplete.
+    /// Later edits to the resource are applied whenever it emits `changed`.
+    #[func]
+    pub fn set_profile(&mut self, profile: Option<Gd<LipSyncProfile>>) {
+        let callable = Callable::from_object_method(self.base.clone(), ON_PROFILE_CHANGED);
+        if let Some(old) = self.profile.take() {
+            let mut old = old.upcast::<Resource>();
+            if old.is_connected(RESOURCE_CHANGED.into(), callable.clone()) {
+                old.disconnect(RESOURCE_CHANGED.into(), callable.clone());
+            }
+        }
+        if let Some(new) = profile.as_ref() {
+            // Deferred since `changed` is emitted while the resource is still being edited
+            new.clone()
+                .upcast::<Resource>()
+                .connect_ex(RESOURCE_CHANGED.into(), callable)
+                .flags(ConnectFlags::CONNECT_DEFERRED.ord() as u32)
+                .done();
+        }
+
+        self.profile = profile;
+        self.reload_profile();
+    }
+
+    #[func]
+    pub fn on_profile_changed(&mut self) {
+        self.reload_profile();
+    }
+
+    /// Loads phoneme templates from a JSON file, see `get_profile_json` for the layout. Only
+    /// used while `profile` is null, an empty path restoring the built-in profile. The current
+    /// profile is kept and `error` is emitted if the file cannot be read.
+    #[func]
+    pub fn set_profile_path(&mut self, path: GString) {
+        self.profile_path = path;
+        self.reload_profile();
+    }
+
+    /// Returns the profile in use as JSON, a starting point for custom profiles.
+    #[func]
+    pub fn get_profile_json(&self) -> GString {
+        let profile = self.analyzer.profile().cloned().unwrap_or_default();
+        GString::from(profile::to_json(&profile))
+    }
+
+    /// Samples submitted so far, or the position right after the last timestamped frame.
+    #[func]
+    pub fn get_sample_position(&self) -> i64 {
+        self.analyzer.sample_position() as i64
+    }
+
+    /// Identifies this node's stream in the shared worker pool.
+    #[func]
+    pub fn get_stream_id(&self) -> i64 {
+        self.analyzer.stream_id().map_or(-1, |v| v as i64)
+    }
+
+    /// Number of input frames waiting to be analyzed.
+    #[func]
+    pub fn get_queue_depth(&self) -> i64 {
+        self.analyzer.queue_depth() as i64
+    }
+
+    /// Input frames dropped because the worker fell behind.
+    #[func]
+    pub fn get_dropped_input_frames(&self) -> i64 {
+        self.analyzer.dropped_inputs() as i64
+    }
+
+    /// Results dropped because `poll` was not called often enough.
+    #[func]
+    pub fn get_dropped_output_frames(&self) -> i64 {
+        self.analyzer.dropped_outputs() as i64
+    }
+
+    /// Sets how many input frames can be queued before the overflow policy applies.
+    #[func]
+    pub fn set_queue_capacity(&mut self, capacity: i64) {
+        self.analyzer.set_queue_capacity(capacity.max(1) as usize);
+    }
+
+    /// 0 drops the oldest queued frame, 1 drops the newest frame, 2 keeps only the newest frame.
+    /// Returns `ERR_INVALID_PARAMETER` and keeps the current policy for other values.
+    #[func]
+    pub fn set_overflow_policy(&mut self, policy: i32) -> Error {
+        let result = OverflowPolicy::try_from(policy).map(|v| self.analyzer.set_overflow_policy(v));
+        to_code(result)
+    }
+
+    /// Sets how many panics in the analysis are recovered from before the stream shuts down.
+    #[func]
+    pub fn set_max_restarts(&mut self, max_restarts: i64) {
+        self.analyzer.set_max_restarts(max_restarts.max(0) as u32);
+    }
+
+    /// Analyzes a whole WAV file every `bake_hop` seconds using the current settings and
+    /// profile. Returns null and emits `error` if the file cannot be read or analyzed.
+    #[func]
+    pub fn bake_file(&mut self, path: GString) -> Option<Gd<LipSyncTimeline>> {
+        let profile = self.analyzer.profile().cloned().unwrap_or_default();
+        let result = read_wav(path)
+            .and_then(|v| bake::bake_wav(&v, self.bake_hop, self.settings(), profile));
+
+        match result {
+            Ok(v) => Some(LipSyncTimeline::from_timeline(&v)),
             Err(e) => {
-                if e == mpsc::TryRecvError::Disconnected {
-                    // godot_print!("Emitted signal: {:?}", LIP_SYNC_PANICKED);
+                self.emit_error(&e);
+                None
+            }
+        }
+    }
 
-                    self.base
-                        .emit_signal(LIP_SYNC_PANICKED.into(), &[Variant::from(format!("{}", e))]);
-                }
+    /// Like `bake_file`, but aligns the vowels expected from `text` against the analysis, which
+    /// is more accurate for scripted lines. `language` selects the spelling rules: `en`, `es`
+    /// or `ja` for romaji.
+    #[func]
+    pub fn bake_file_with_transcript(
+        &mut self,
+        path: GString,
+        text: GString,
+        language: GString,
+    ) -> Option<Gd<LipSyncTimeline>> {
+        let rules = match transcript::rules_for(&language.to_string()) {
+            Some(v) => v,
+            None => {
+                self.emit_error(&LipSyncError::Configuration(format!(
+                    "No transcript rules for language '{}'",
+                    language
+                )));
+                return None;
+            }
+        };
+
+        let profile = self.analyzer.profile().cloned().unwrap_or_default();
+        let text = text.to_string();
+        let line = Transcript {
+            text: &text,
+            rules,
+            strength: self.transcript_strength,
+        };
+        let result = read_wav(path).and_then(|v| {
+            bake::bake_wav_transcript(&v, self.bake_hop, self.settings(), profile, &line)
+        });
+
+        match result {
+            Ok(v) => Some(LipSyncTimeline::from_timeline(&v)),
+            Err(e) => {
+                self.emit_error(&e);
+                None
+            }
+        }
+    }
+
+    /// Returns frame counts, queue state and per-stage timings of the analysis. Timings are in
+    /// microseconds, averaged over all frames unless suffixed with `_last`.
+    #[func]
+    pub fn get_stats(&self) -> Dictionary {
+        let stats = self.analyzer.stats();
+
+        let mut dict = Dictionary::from(&stats);
+        dict.insert("queue_depth", self.get_queue_depth());
+        dict.insert("dropped_input_frames", self.get_dropped_input_frames());
+        dict.insert("dropped_output_frames", self.get_dropped_output_frames());
+        dict.insert("coalesced_results", self.coalesced_results as i64);
+        dict.insert("dropped_tap_samples", self.analyzer.tap_overruns() as i64);
+
+        dict
+    }
+
+    /// Returns the buffers of the latest analyzed frame: the `windowed` samples, FFT
+    /// `magnitude`, `log_spectrum`, liftered `envelope`, detected and averaged peaks, phoneme
+    /// `distances`, the frame's own `estimate`, the smoothed `vowel` and `amount`. Empty until
+    /// `debug_capture` is on and a frame has been analyzed. An `estimate` of -1 with other than 3
+    /// or 4 peaks means the envelope had no usable formants, typically from `peak_threshold` or
+    /// the filter range.
+    #[func]
+    pub fn get_debug_frame(&self) -> Dictionary {
+        let frame = self.analyzer.debug_frame();
+
+        frame
+            .as_ref()
+            .map_or_else(Dictionary::new, Dictionary::from)
+    }
+
+    /// Returns a single value from `get_stats` as a float, used by `Performance` monitors.
+    #[func]
+    pub fn get_stat(&self, key: GString) -> f64 {
+        match self.get_stats().get(key) {
+            Some(v) => match v.try_to::<f64>() {
+                Ok(v) => v,
+                Err(_) => v.try_to::<i64>().unwrap_or(0) as f64,
+            },
+            None => 0.0,
+        }
+    }
+
+    #[func]
+    pub fn set_performance_monitors(&mut self, enabled: bool) {
+        self.performance_monitors = enabled;
+        if enabled && self.base.is_inside_tree() {
+            self.register_monitors();
+        } else {
+            self.unregister_monitors();
+        }
+    }
+
+    #[func]
+    pub fn set_debug_capture(&mut self, enabled: bool) {
+        self.debug_capture = enabled;
+        if let Err(e) = self.analyzer.set_debug(enabled) {
+            self.emit_error(&e);
+        }
+    }
+
+    #[func]
+    pub fn set_channel_mode(&mut self, channel_mode: i32) {
+        self.channel_mode = channel_mode;
+        if let Err(e) = self.attach_tap() {
+            self.emit_error(&e);
+        }
+    }
+
+    /// Starts analyzing the audio of the `LipSyncAudioEffect` whose `tap_name` is `name`. In
+    /// threaded mode frames are read on the worker pool as soon as they are mixed, otherwise
+    /// they are read by `poll`.
+    #[func]
+    pub fn set_bus_tap(&mut self, name: GString) {
+        self.bus_tap = name;
+        if let Err(e) = self.attach_tap() {
+            self.emit_error(&e);
+        }
+    }
+
+    /// Switches between the worker pool and analyzing on the calling thread. Results still
+    /// pending on the worker are discarded.
+    #[func]
+    pub fn set_threaded(&mut self, threaded: bool) {
+        self.threaded = threaded;
+        self.events.reset();
+
+        if let Err(e) = self.start() {
+            self.emit_error(&e);
+        }
+    }
+
+    /// Analyzes samples right away and returns the result, only available when not threaded.
+    /// Returns an empty `Dictionary` and emits `error` on failure.
+    #[func]
+    pub fn process_now(&mut self, samples: Array<f32>) -> Dictionary {
+        let data: Vec<f32> = samples.iter_shared().collect();
+
+        match self.analyzer.process(data, None) {
+            Ok(v) => {
+                self.emit_events(&v);
+                estimate_to_dictionary(v, self.output_format())
+            }
+            Err(e) => {
+                self.emit_error(&e);
+                Dictionary::new()
             }
         }
     }
 
+    /// Emits every result waiting on the worker, or analyzes the frames waiting in the tap when
+    /// not threaded. Errors are emitted as they are found. Returns how many results were
+    /// skipped by `coalesce_results`.
+    #[func]
+    pub fn poll(&mut self) -> i64 {
+        let results = self.analyzer.poll();
+        self.emit_results(results)
+    }
+
     #[func]
-    pub fn shutdown(&mut self) {
-        self.sender.send(JobMessage::Shutdown).expect("When shutting down thread because of invalid message, encountered error. Shutting down anyways.");
-        self.join_handle
-            .take()
-            .expect("Unable to take join_handle")
-            .join()
-            .expect("Unable to join thread");
+    pub fn shutdown(&mut self) -> Error {
+        to_code(self.analyzer.stop())
     }
+
+    /// Restarts the analyzer on the worker pool or the calling thread, depending on `threaded`.
+    fn start(&mut self) -> Result<(), LipSyncError> {
+        self.analyzer.start(self.threaded)?;
+
+        // Properties are set one at a time, so the settings may be invalid for a while. The
+        // analyzer then keeps its previous settings until they are corrected
+        self.analyzer.configure(self.settings())
+    }
+
+    /// Hands the current tap to the analyzer, dropping any previous one.
+    fn attach_tap(&mut self) -> Result<(), LipSyncError> {
+        let tap = if self.bus_tap.is_empty() {
+            None
+        } else {
+            Some(tap::get(&self.bus_tap.to_string()))
+        };
+
+        self.analyzer
+            .set_tap(tap, ChannelMode::from(self.channel_mode))
+    }
+
+    /// Emits `error` for each failed frame and `updated` for each result in the order they were
+    /// produced, or `updated` only for the last result with `coalesce_results`. Events are
+    /// derived from every result either way. Returns the number of skipped results.
+    fn emit_results(&mut self, results: Vec<Result<VowelEstimate, LipSyncError>>) -> i64 {
+        let last = results.iter().rposition(|v| v.is_ok());
+        let coalesced = if self.coalesce_results {
+            results
+                .iter()
+                .filter(|v| v.is_ok())
+                .count()
+                .saturating_sub(1)
+        } else {
+            0
+        };
+
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Err(e) => self.emit_error(&e),
+                Ok(v) if !self.coalesce_results => self.emit_updated(v, None),
+                Ok(v) if Some(i) == last => self.emit_updated(v, Some(coalesced as i64)),
+                Ok(v) => self.emit_events(&v),
+            }
+        }
+        self.coalesced_results += coalesced as u64;
+
+        coalesced as i64
+    }
+
+    fn output_format(&self) -> OutputFormat {
+        OutputFormat::from(self.output_format)
+    }
+
+    fn settings(&self) -> LipSyncSettings {
+        LipSyncSettings {
+            fft_samples: self.fft_samples.max(0) as usize,
+            dynamic_range: self.dynamic_range,
+            peak_threshold: self.peak_threshold,
+            lifter_level: self.lifter_level,
+            filter_low: self.filter_low,
+            filter_high: self.filter_high,
+            compare_method: CompareMethod::from(self.compare_method),
+            attack_time: self.attack_time,
+            release_time: self.release_time,
+        }
+    }
+
+    fn load_settings(&mut self, settings: &LipSyncSettings) {
+        self.fft_samples = settings.fft_samples as i64;
+        self.dynamic_range = settings.dynamic_range;
+        self.peak_threshold = settings.peak_threshold;
+        self.lifter_level = settings.lifter_level;
+        self.filter_low = settings.filter_low;
+        self.filter_high = settings.filter_high;
+        self.compare_method = settings.compare_method.into();
+        self.attack_time = settings.attack_time;
+        self.release_time = settings.release_time;
+    }
+
+    /// Validates the settings and hands them to the analyzer, keeping the old ones on failure.
+    fn apply_settings(&mut self, settings: LipSyncSettings) -> Result<(), LipSyncError> {
+        self.analyzer.configure(settings.clone())?;
+        self.load_settings(&settings);
+
+        Ok(())
+    }
+
+    /// Applies the profile from `profile` or `profile_path`.
+    fn reload_profile(&mut self) {
+        let profile = if let Some(resource) = self.profile.as_ref() {
+            resource.bind().to_profile()
+        } else if self.profile_path.is_empty() {
+            Ok(Profile::default())
+        } else {
+            let text = FileAccess::get_file_as_string(self.profile_path.clone()).to_string();
+            profile::parse(&text).map_err(|e| {
+                LipSyncError::Profile(format!("Unable to load {}: {}", self.profile_path, e))
+            })
+        };
+
+        if let Err(e) = profile.and_then(|v| self.analyzer.set_profile(v)) {
+            self.emit_error(&e);
+        }
+    }
+
+    fn apply_or_emit(&mut self, settings: LipSyncSettings) {
+        if let Err(e) = self.apply_settings(settings) {
+            self.emit_error(&e);
+        }
+    }
+
+    fn send_pcm(&mut self, bytes: &[u8], format: i32, channels: i64) -> Result<(), LipSyncError> {
+        if channels < 1 {
+            return Err(LipSyncError::InvalidInput(format!(
+                "Invalid channel count {}",
+                channels
+            )));
+        }
+
+        let format = PcmFormat::try_from(format)?;
+        let samples = pcm::decode(bytes, format, channels as usize)?;
+        let data = downmix(
+            samples.as_slice(),
+            channels as usize,
+            ChannelMode::from(self.channel_mode),
+        );
+        self.send_samples(data, None)
+    }
+
+    /// Queues samples on the analyzer. When not threaded they are analyzed right away, so the
+    /// result is emitted before returning.
+    fn send_samples(
+        &mut self,
+        data: Vec<f32>,
+        start_time: Option<f64>,
+    ) -> Result<(), LipSyncError> {
+        self.analyzer.push(data, start_time)?;
+        if !self.threaded {
+            self.poll();
+        }
+
+        Ok(())
+    }
+
+    fn register_monitors(&mut self) {
+        self.unregister_monitors();
+
+        let mut performance = Performance::singleton();
+        let prefix = format!(
+            "LipSync/{}_{}",
+            self.base.get_name(),
+            self.base.instance_id().to_i64()
+        );
+        for stat in MONITORED_STATS {
+            let id = StringName::from(format!("{}/{}", prefix, stat));
+            let callable = Callable::from_object_method(self.base.clone(), GET_STAT)
+                .bindv(varray![GString::from(stat)]);
+            performance.add_custom_monitor(id.clone(), callable);
+            self.registered_monitors.push(id);
+        }
+    }
+
+    fn unregister_monitors(&mut self) {
+        let mut performance = Performance::singleton();
+        for id in self.registered_monitors.drain(..) {
+            if performance.has_custom_monitor(id.clone()) {
+                performance.remove_custom_monitor(id);
+            }
+        }
+    }
+
+    /// Emits a result and the events it causes, with the number of results skipped before it
+    /// as `coalesced` if any were.
+    fn emit_updated(&mut self, v: VowelEstimate, coalesced: Option<i64>) {
+        self.emit_events(&v);
+        let mut dict = estimate_to_dictionary(v, self.output_format());
+        if let Some(coalesced) = coalesced {
+            dict.insert("coalesced", coalesced);
+        }
+        self.base
+            .emit_signal(LIP_SYNC_UPDATED.into(), &[Variant::from(dict)]);
+    }
+
+    fn emit_events(&mut self, v: &VowelEstimate) {
+        let settings = EventSettings {
+            speech_threshold: self.speech_threshold,
+            speech_start_delay: self.speech_start_delay,
+            speech_end_delay: self.speech_end_delay,
+            phoneme_hold: self.phoneme_hold,
+            level_peak_threshold: self.level_peak_threshold,
+        };
+
+        for event in self.events.push(v, &settings) {
+            match event {
+                LipSyncEvent::PhonemeChanged { old, new } => self.base.emit_signal(
+                    LIP_SYNC_PHONEME_CHANGED.into(),
+                    &[Variant::from(old as i64), Variant::from(new as i64)],
+                ),
+                LipSyncEvent::SpeechStarted => {
+                    self.base.emit_signal(LIP_SYNC_SPEECH_STARTED.into(), &[])
+                }
+                LipSyncEvent::SpeechEnded { duration } => self
+                    .base
+                    .emit_signal(LIP_SYNC_SPEECH_ENDED.into(), &[Variant::from(duration)]),
+                LipSyncEvent::LevelPeaked { amount } => self
+                    .base
+                    .emit_signal(LIP_SYNC_LEVEL_PEAKED.into(), &[Variant::from(amount)]),
+            };
+        }
+    }
+
+    fn emit_error(&mut self, e: &LipSyncError) {
+        self.base
+            .emit_signal(LIP_SYNC_ERROR.into(), &[Variant::from(Dictionary::from(e))]);
+    }
+}
+
+/// Reads and decodes a WAV file through Godot's file system, so `res://` paths work.
+fn read_wav(path: GString) -> Result<Wav, LipSyncError> {
+    if !FileAccess::file_exists(path.clone()) {
+        return Err(LipSyncError::InvalidInput(format!(
+            "File not found: {}",
+            path
+        )));
+    }
+
+    let bytes = FileAccess::get_file_as_bytes(path.clone());
+    if bytes.is_empty() {
+        return Err(LipSyncError::InvalidInput(format!(
+            "Unable to read {}: {:?}",
+            path,
+            FileAccess::get_open_error()
+        )));
+    }
+
+    wav::parse(bytes.as_slice())
 }
 
 #[godot_api]
 impl INode for LipSyncRs {
     fn init(base: Base<Self::Base>) -> Self {
-        let (jh, s, r) = job::create_job().expect("Unable to create job thread");
+        let settings = LipSyncSettings::default();
+        let sample_rate = AudioServer::singleton().get_mix_rate() as f64;
 
-        LipSyncRs {
-            join_handle: Some(jh),
-            sender: s,
-            receiver: r,
+        let mut lip_sync = LipSyncRs {
+            threaded: true,
+            analyzer: Analyzer::new(sample_rate),
+            pending_error: None,
+            sample_rate,
+            profile: None,
+            profile_path: GString::new(),
+            output_format: OutputFormat::Full.into(),
+            speech_threshold: events::DEFAULT_SPEECH_THRESHOLD,
+            speech_start_delay: events::DEFAULT_SPEECH_START_DELAY,
+            speech_end_delay: events::DEFAULT_SPEECH_END_DELAY,
+            phoneme_hold: events::DEFAULT_PHONEME_HOLD,
+            level_peak_threshold: events::DEFAULT_LEVEL_PEAK_THRESHOLD,
+            events: EventDetector::new(),
+            fft_samples: 0,
+            dynamic_range: 0.0,
+            peak_threshold: 0.0,
+            lifter_level: 0,
+            filter_low: 0,
+            filter_high: 0,
+            compare_method: 0,
+            attack_time: 0.0,
+            release_time: 0.0,
+            channel_mode: ChannelMode::Mid.into(),
+            bus_tap: GString::new(),
+            bake_hop: 1.0 / 60.0,
+            transcript_strength: DEFAULT_TRANSCRIPT_STRENGTH,
+            performance_monitors: false,
+            debug_capture: false,
+            auto_poll: false,
+            coalesce_results: false,
+            coalesced_results: 0,
+            registered_monitors: vec![],
             base,
+        };
+        lip_sync.load_settings(&settings);
+        lip_sync.pending_error = lip_sync.start().err();
+
+        lip_sync
+    }
+
+    fn ready(&mut self) {
+        if let Some(e) = self.pending_error.take() {
+            self.emit_error(&e);
         }
     }
+
+    fn process(&mut self, _delta: f64) {
+        if self.auto_poll {
+            self.poll();
+        }
+    }
+
+    fn enter_tree(&mut self) {
+        if self.performance_monitors {
+            self.register_monitors();
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        self.unregister_monitors();
+    }
 }
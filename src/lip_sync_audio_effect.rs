@@ -0,0 +1,77 @@
+use godot::engine::{native::AudioFrame, AudioEffect, AudioEffectInstance};
+use godot::prelude::*;
+use std::ffi::c_void;
+
+use crate::analysis::tap::{self, TapWriter};
+
+/// Audio bus effect that forwards the mixed audio to the `LipSyncRs` node whose `bus_tap`
+/// matches `tap_name`, without going through GDScript. The audio passes through unchanged. A tap
+/// feeds a single node, other nodes set to the same tap get no audio.
+#[derive(GodotClass)]
+#[class(base = AudioEffect)]
+pub struct LipSyncAudioEffect {
+    #[export]
+    tap_name: GString,
+    #[base]
+    base: Base<AudioEffect>,
+}
+
+impl LipSyncAudioEffect {
+    pub fn with_tap(tap_name: &str) -> Gd<Self> {
+        Gd::from_init_fn(|base| LipSyncAudioEffect {
+            tap_name: GString::from(tap_name),
+            base,
+        })
+    }
+}
+
+#[godot_api]
+impl IAudioEffect for LipSyncAudioEffect {
+    fn init(base: Base<Self::Base>) -> Self {
+        LipSyncAudioEffect {
+            tap_name: GString::from("lip_sync"),
+            base,
+        }
+    }
+
+    fn instantiate(&mut self) -> Option<Gd<AudioEffectInstance>> {
+        let writer = TapWriter::new(tap::get(&self.tap_name.to_string()));
+        if writer.is_none() {
+            godot_print!(
+                "LipSyncAudioEffect tap '{}' is already being written",
+                self.tap_name
+            );
+        }
+
+        let instance = Gd::from_init_fn(|base| LipSyncAudioEffectInstance { writer, base });
+        Some(instance.upcast())
+    }
+}
+
+#[derive(GodotClass)]
+#[class(init, base = AudioEffectInstance)]
+pub struct LipSyncAudioEffectInstance {
+    writer: Option<TapWriter>,
+    #[base]
+    base: Base<AudioEffectInstance>,
+}
+
+#[godot_api]
+impl IAudioEffectInstance for LipSyncAudioEffectInstance {
+    /// Runs on the audio thread, so it only copies frames into the tap's lock-free ring.
+    unsafe fn process(
+        &mut self,
+        src_buffer: *const c_void,
+        dst_buffer: *mut AudioFrame,
+        frame_count: i32,
+    ) {
+        let count = frame_count.max(0) as usize;
+        let src = std::slice::from_raw_parts(src_buffer as *const AudioFrame, count);
+        let dst = std::slice::from_raw_parts_mut(dst_buffer, count);
+        dst.copy_from_slice(src);
+
+        if let Some(writer) = self.writer.as_ref() {
+            writer.write(src.iter().flat_map(|v| [v.left, v.right]));
+        }
+    }
+}
@@ -0,0 +1,574 @@
+use godot::engine::global::MouseButton;
+use godot::engine::{
+    control::SizeFlags, editor_plugin::DockSlot, AudioServer, AudioStreamMicrophone,
+    AudioStreamPlayer, Button, CheckButton, Control, EditorPlugin, HBoxContainer, IControl,
+    IEditorPlugin, IVBoxContainer, InputEvent, InputEventMouseButton, InputEventMouseMotion, Label,
+    OptionButton, ProjectSettings, VBoxContainer,
+};
+use godot::prelude::*;
+
+use crate::{
+    analysis::{
+        debug::DebugFrame,
+        input::ChannelMode,
+        job::Job,
+        model::DataPoint,
+        tap::{self, TapReader},
+    },
+    lip_sync_audio_effect::LipSyncAudioEffect,
+    lip_sync_profile::LipSyncProfile,
+};
+
+/// Bus created while listening, muted so the microphone is not played back.
+const CALIBRATION_BUS: &str = "LipSyncCalibration";
+const CALIBRATION_TAP: &str = "lip_sync_calibration";
+/// Amplitudes drawn at the top of the view, peaks are relative to the first one so they can
+/// go above 1.
+const MAX_AMPLITUDE: f32 = 1.5;
+/// Distance in pixels within which a template peak can be grabbed.
+const GRAB_RADIUS: f32 = 8.0;
+/// Frames quieter than this are not recorded into templates.
+const MIN_RECORD_AMOUNT: f32 = 0.3;
+
+/// Adds the calibration dock, which edits the `LipSyncProfile` selected in the inspector.
+#[derive(GodotClass)]
+#[class(tool, init, editor_plugin, base = EditorPlugin)]
+pub struct LipSyncCalibrationPlugin {
+    dock: Option<Gd<LipSyncCalibrationDock>>,
+    #[base]
+    base: Base<EditorPlugin>,
+}
+
+#[godot_api]
+impl IEditorPlugin for LipSyncCalibrationPlugin {
+    fn enter_tree(&mut self) {
+        let mut dock = Gd::<LipSyncCalibrationDock>::new_default();
+        dock.set_name("Lip Sync".into());
+        self.base
+            .add_control_to_dock(DockSlot::DOCK_SLOT_RIGHT_UL, dock.clone().upcast());
+        self.dock = Some(dock);
+    }
+
+    fn exit_tree(&mut self) {
+        if let Some(mut dock) = self.dock.take() {
+            dock.bind_mut().stop_listening();
+            self.base.remove_control_from_docks(dock.clone().upcast());
+            dock.queue_free();
+        }
+    }
+
+    fn handles(&self, object: Gd<Object>) -> bool {
+        object.try_cast::<LipSyncProfile>().is_some()
+    }
+
+    fn edit(&mut self, object: Option<Gd<Object>>) {
+        let profile = object.and_then(|v| v.try_cast::<LipSyncProfile>());
+        if let Some(dock) = self.dock.as_mut() {
+            dock.bind_mut().set_profile(profile);
+        }
+    }
+}
+
+/// Sums of the peaks recorded for the selected phoneme.
+#[derive(Default)]
+struct Recording {
+    peaks3: Vec<DataPoint>,
+    frames3: u32,
+    peaks4: Vec<DataPoint>,
+    frames4: u32,
+}
+
+impl Recording {
+    fn add(&mut self, peaks: &[DataPoint]) {
+        let (sums, frames) = match peaks.len() {
+            3 => (&mut self.peaks3, &mut self.frames3),
+            4 => (&mut self.peaks4, &mut self.frames4),
+            _ => return,
+        };
+        if sums.is_empty() {
+            *sums = vec![DataPoint(0.0, 0.0); peaks.len()];
+        }
+        for (sum, peak) in sums.iter_mut().zip(peaks.iter()) {
+            sum.0 += peak.0;
+            sum.1 += peak.1;
+        }
+        *frames += 1;
+    }
+
+    /// Averaged peaks for each peak count that was recorded at least once.
+    fn averages(&self) -> Vec<Vec<DataPoint>> {
+        [(&self.peaks3, self.frames3), (&self.peaks4, self.frames4)]
+            .into_iter()
+            .filter(|(_, frames)| *frames > 0)
+            .map(|(sums, frames)| {
+                let inv = 1.0 / frames as f32;
+                sums.iter()
+                    .map(|v| DataPoint(v.0 * inv, v.1 * inv))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Listens to the microphone, shows what the analysis sees and records phoneme templates into
+/// the edited profile.
+#[derive(GodotClass)]
+#[class(tool, base = VBoxContainer)]
+pub struct LipSyncCalibrationDock {
+    profile: Option<Gd<LipSyncProfile>>,
+    view: Option<Gd<LipSyncCalibrationView>>,
+    phoneme_select: Option<Gd<OptionButton>>,
+    record_button: Option<Gd<Button>>,
+    status: Option<Gd<Label>>,
+    player: Option<Gd<AudioStreamPlayer>>,
+    job: Job,
+    reader: Option<TapReader>,
+    recording: Option<Recording>,
+    #[base]
+    base: Base<VBoxContainer>,
+}
+
+#[godot_api]
+impl LipSyncCalibrationDock {
+    pub fn set_profile(&mut self, profile: Option<Gd<LipSyncProfile>>) {
+        self.profile = profile;
+        self.recording = None;
+        if let Some(button) = self.record_button.as_mut() {
+            button.set_pressed_no_signal(false);
+        }
+
+        let mut names = vec![];
+        match self.profile.as_ref().map(|v| v.bind().to_profile()) {
+            Some(Ok(v)) => {
+                names = v.phonemes.clone();
+                self.job.set_profile(v);
+            }
+            Some(Err(e)) => self.set_status(&e.to_string()),
+            None => {}
+        }
+        if let Some(select) = self.phoneme_select.as_mut() {
+            select.clear();
+            for name in names.iter() {
+                select.add_item(GString::from(name.as_str()));
+            }
+        }
+
+        let phoneme = self.selected_phoneme();
+        if let Some(view) = self.view.as_mut() {
+            view.bind_mut().set_profile(self.profile.clone(), phoneme);
+        }
+    }
+
+    #[func]
+    pub fn on_listen_toggled(&mut self, listening: bool) {
+        if listening {
+            self.start_listening();
+        } else {
+            self.stop_listening();
+        }
+    }
+
+    #[func]
+    pub fn on_phoneme_selected(&mut self, _index: i64) {
+        let phoneme = self.selected_phoneme();
+        if let Some(view) = self.view.as_mut() {
+            view.bind_mut().set_profile(self.profile.clone(), phoneme);
+        }
+    }
+
+    /// Starts averaging peaks while pressed, and writes them into the selected phoneme's
+    /// templates once released.
+    #[func]
+    pub fn on_record_toggled(&mut self, recording: bool) {
+        if recording {
+            self.recording = Some(Recording::default());
+            return;
+        }
+
+        let recording = match self.recording.take() {
+            Some(v) => v,
+            None => return,
+        };
+        let phoneme = self.selected_phoneme();
+        let mut profile = match self.profile.clone() {
+            Some(v) => v,
+            None => {
+                self.set_status("Select a LipSyncProfile to record into");
+                return;
+            }
+        };
+
+        let averages = recording.averages();
+        for peaks in averages.iter() {
+            for (i, peak) in peaks.iter().enumerate() {
+                profile
+                    .bind_mut()
+                    .set_template_peak(phoneme, peaks.len(), i, peak.clone());
+            }
+        }
+        self.on_template_changed();
+
+        let frames = recording.frames3 + recording.frames4;
+        self.set_status(&format!("Recorded {} frames", frames));
+    }
+
+    /// Sends the edited templates to the job so the distances reflect them.
+    #[func]
+    pub fn on_template_changed(&mut self) {
+        let profile = self.profile.as_ref().map(|v| v.bind().to_profile());
+        match profile {
+            Some(Ok(v)) => self.job.set_profile(v),
+            Some(Err(e)) => self.set_status(&e.to_string()),
+            None => {}
+        }
+    }
+
+    pub fn stop_listening(&mut self) {
+        self.reader = None;
+        if let Some(mut player) = self.player.take() {
+            player.stop();
+            player.queue_free();
+        }
+
+        let mut server = AudioServer::singleton();
+        let bus = server.get_bus_index(CALIBRATION_BUS.into());
+        if bus >= 0 {
+            server.remove_bus(bus);
+        }
+    }
+
+    fn start_listening(&mut self) {
+        let enabled = ProjectSettings::singleton()
+            .get_setting("audio/driver/enable_input".into())
+            .try_to::<bool>()
+            .unwrap_or(false);
+        if !enabled {
+            self.set_status("Enable audio/driver/enable_input in the project settings");
+        }
+
+        let mut server = AudioServer::singleton();
+        if server.get_bus_index(CALIBRATION_BUS.into()) < 0 {
+            server.add_bus();
+            let bus = server.get_bus_count() - 1;
+            server.set_bus_name(bus, CALIBRATION_BUS.into());
+            server.set_bus_mute(bus, true);
+            server.add_bus_effect(bus, LipSyncAudioEffect::with_tap(CALIBRATION_TAP).upcast());
+        }
+
+        let mut player = AudioStreamPlayer::new_alloc();
+        player.set_stream(AudioStreamMicrophone::new_gd().upcast());
+        player.set_bus(CALIBRATION_BUS.into());
+        self.base.add_child(player.clone().upcast());
+        player.play();
+
+        self.reader = Some(TapReader::new(
+            tap::get(CALIBRATION_TAP),
+            ChannelMode::Mid,
+            server.get_mix_rate() as f64,
+        ));
+        self.job.set_debug(true);
+        self.player = Some(player);
+    }
+
+    fn selected_phoneme(&self) -> usize {
+        self.phoneme_select
+            .as_ref()
+            .map_or(0, |v| v.get_selected().max(0) as usize)
+    }
+
+    fn set_status(&mut self, text: &str) {
+        if let Some(status) = self.status.as_mut() {
+            status.set_text(text.into());
+        }
+    }
+
+    fn connect<T: Inherits<Object>>(&self, node: &mut Gd<T>, signal: &str, method: &str) {
+        let callable = Callable::from_object_method(self.base.clone(), method);
+        node.clone()
+            .upcast::<Object>()
+            .connect(signal.into(), callable);
+    }
+}
+
+#[godot_api]
+impl IVBoxContainer for LipSyncCalibrationDock {
+    fn init(base: Base<Self::Base>) -> Self {
+        LipSyncCalibrationDock {
+            profile: None,
+            view: None,
+            phoneme_select: None,
+            record_button: None,
+            status: None,
+            player: None,
+            job: Job::new(),
+            reader: None,
+            recording: None,
+            base,
+        }
+    }
+
+    fn ready(&mut self) {
+        let mut toolbar = HBoxContainer::new_alloc();
+
+        let mut listen = CheckButton::new_alloc();
+        listen.set_text("Listen".into());
+        self.connect(&mut listen, "toggled", "on_listen_toggled");
+        toolbar.add_child(listen.upcast());
+
+        let mut select = OptionButton::new_alloc();
+        self.connect(&mut select, "item_selected", "on_phoneme_selected");
+        toolbar.add_child(select.clone().upcast());
+
+        let mut record = Button::new_alloc();
+        record.set_text("Record".into());
+        record.set_toggle_mode(true);
+        self.connect(&mut record, "toggled", "on_record_toggled");
+        toolbar.add_child(record.clone().upcast());
+
+        let mut view = Gd::<LipSyncCalibrationView>::new_default();
+        view.set_custom_minimum_size(Vector2::new(0.0, 240.0));
+        view.set_v_size_flags(SizeFlags::SIZE_EXPAND_FILL);
+        self.connect(&mut view, "template_changed", "on_template_changed");
+
+        let mut status = Label::new_alloc();
+        status.set_text("Select a LipSyncProfile to calibrate it".into());
+
+        self.base.add_child(toolbar.upcast());
+        self.base.add_child(view.clone().upcast());
+        self.base.add_child(status.clone().upcast());
+
+        self.phoneme_select = Some(select);
+        self.record_button = Some(record);
+        self.view = Some(view);
+        self.status = Some(status);
+
+        let profile = self.profile.clone();
+        self.set_profile(profile);
+    }
+
+    fn process(&mut self, _delta: f64) {
+        let mut reader = match self.reader.take() {
+            Some(v) => v,
+            None => return,
+        };
+
+        reader.frame_size = self.job.frame_size();
+        let mut last = None;
+        while let Some(frame) = reader.next_frame() {
+            if self.job.execute(frame.samples.as_slice()).is_err() {
+                continue;
+            }
+            let debug = match self.job.debug_frame() {
+                Some(v) => v.clone(),
+                None => continue,
+            };
+            if let Some(recording) = self.recording.as_mut() {
+                if debug.amount >= MIN_RECORD_AMOUNT {
+                    recording.add(debug.peaks.as_slice());
+                }
+            }
+            last = Some(debug);
+        }
+        self.reader = Some(reader);
+
+        if let Some(debug) = last {
+            let names = self.job.phonemes().to_vec();
+            let estimate = debug.estimate;
+            let vowel = names
+                .get(estimate.max(0) as usize)
+                .filter(|_| estimate >= 0);
+            self.set_status(&format!(
+                "{} peaks, vowel {}, amount {:.2}",
+                debug.peaks.len(),
+                vowel.map_or("-", |v| v.as_str()),
+                debug.amount
+            ));
+            if let Some(view) = self.view.as_mut() {
+                view.bind_mut().set_frame(debug, names);
+            }
+        }
+    }
+}
+
+/// Draws the spectral envelope, detected peaks and distances of the last frame, along with the
+/// selected phoneme's templates which can be dragged to tune them.
+#[derive(GodotClass)]
+#[class(tool, base = Control)]
+pub struct LipSyncCalibrationView {
+    frame: Option<DebugFrame>,
+    phonemes: Vec<String>,
+    profile: Option<Gd<LipSyncProfile>>,
+    phoneme: usize,
+    /// Template peak being dragged, as peak count and index
+    dragging: Option<(usize, usize)>,
+    #[base]
+    base: Base<Control>,
+}
+
+#[godot_api]
+impl LipSyncCalibrationView {
+    /// Emitted after a template peak was dragged to a new position.
+    #[signal]
+    fn template_changed();
+
+    pub fn set_profile(&mut self, profile: Option<Gd<LipSyncProfile>>, phoneme: usize) {
+        self.profile = profile;
+        self.phoneme = phoneme;
+        self.dragging = None;
+        self.base.queue_redraw();
+    }
+
+    pub fn set_frame(&mut self, frame: DebugFrame, phonemes: Vec<String>) {
+        self.frame = Some(frame);
+        self.phonemes = phonemes;
+        self.base.queue_redraw();
+    }
+
+    /// Number of envelope bins spanning the view's width.
+    fn bins(&self) -> f32 {
+        self.frame.as_ref().map_or(0, |v| v.envelope.len()).max(2) as f32
+    }
+
+    fn to_screen(&self, bin: f32, amplitude: f32) -> Vector2 {
+        let size = self.base.get_size();
+        Vector2::new(
+            bin / (self.bins() - 1.0) * size.x,
+            size.y * (1.0 - amplitude / MAX_AMPLITUDE),
+        )
+    }
+
+    fn from_screen(&self, position: Vector2) -> DataPoint {
+        let size = self.base.get_size();
+        let bin = (position.x / size.x.max(1.0) * (self.bins() - 1.0)).round();
+        let amplitude = (1.0 - position.y / size.y.max(1.0)) * MAX_AMPLITUDE;
+        DataPoint(bin.max(0.0), amplitude.clamp(0.0, MAX_AMPLITUDE))
+    }
+
+    fn templates(&self) -> Vec<(usize, Vec<DataPoint>)> {
+        match self.profile.as_ref() {
+            Some(profile) => [3, 4]
+                .into_iter()
+                .map(|count| (count, profile.bind().template(self.phoneme, count)))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn peak_at(&self, position: Vector2) -> Option<(usize, usize)> {
+        for (count, template) in self.templates() {
+            for (i, peak) in template.iter().enumerate() {
+                if self.to_screen(peak.0, peak.1).distance_to(position) <= GRAB_RADIUS {
+                    return Some((count, i));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[godot_api]
+impl IControl for LipSyncCalibrationView {
+    fn init(base: Base<Self::Base>) -> Self {
+        LipSyncCalibrationView {
+            frame: None,
+            phonemes: vec![],
+            profile: None,
+            phoneme: 0,
+            dragging: None,
+            base,
+        }
+    }
+
+    fn draw(&mut self) {
+        let size = self.base.get_size();
+        let background = Rect2::new(Vector2::ZERO, size);
+        self.base
+            .draw_rect(background, Color::from_rgb(0.1, 0.1, 0.12));
+
+        if let Some(frame) = self.frame.clone() {
+            // The envelope is scaled to its highest point so its shape can be compared with
+            // peaks, which are relative to the first one
+            let max = frame
+                .envelope
+                .iter()
+                .fold(f32::EPSILON, |acc, v| acc.max(*v));
+            let mut points = PackedVector2Array::new();
+            for (i, v) in frame.envelope.iter().enumerate() {
+                points.push(self.to_screen(i as f32, v / max));
+            }
+            if points.len() > 1 {
+                self.base
+                    .draw_polyline(points, Color::from_rgb(0.5, 0.8, 1.0));
+            }
+
+            for peak in frame.peaks.iter() {
+                let position = self.to_screen(peak.0, peak.1);
+                self.base
+                    .draw_circle(position, 4.0, Color::from_rgb(1.0, 0.85, 0.2));
+            }
+
+            if let Some(font) = self.base.get_theme_default_font() {
+                let scale = frame
+                    .distances
+                    .iter()
+                    .fold(f32::EPSILON, |acc, v| acc.max(*v));
+                for (i, d) in frame.distances.iter().enumerate() {
+                    let name = self.phonemes.get(i).map_or("?", |v| v.as_str());
+                    let y = 16.0 + i as f32 * 16.0;
+                    let color = if i as i32 == frame.estimate {
+                        Color::from_rgb(0.4, 1.0, 0.4)
+                    } else {
+                        Color::from_rgb(0.8, 0.8, 0.8)
+                    };
+                    let bar = Rect2::new(
+                        Vector2::new(24.0, y - 10.0),
+                        Vector2::new(d / scale * 80.0, 10.0),
+                    );
+                    self.base
+                        .draw_string_ex(font.clone(), Vector2::new(4.0, y), name.into())
+                        .modulate(color)
+                        .done();
+                    self.base.draw_rect(bar, color);
+                }
+            }
+        }
+
+        for (count, template) in self.templates() {
+            let color = if count == 3 {
+                Color::from_rgb(1.0, 0.4, 0.4)
+            } else {
+                Color::from_rgb(0.8, 0.4, 1.0)
+            };
+            for peak in template.iter() {
+                let position = self.to_screen(peak.0, peak.1);
+                let handle = Rect2::new(position - Vector2::new(4.0, 4.0), Vector2::new(8.0, 8.0));
+                self.base.draw_rect(handle, color);
+            }
+        }
+    }
+
+    fn gui_input(&mut self, event: Gd<InputEvent>) {
+        if let Some(button) = event.clone().try_cast::<InputEventMouseButton>() {
+            if button.get_button_index() != MouseButton::MOUSE_BUTTON_LEFT {
+                return;
+            }
+            if button.is_pressed() {
+                self.dragging = self.peak_at(button.get_position());
+            } else if self.dragging.take().is_some() {
+                self.base.emit_signal("template_changed".into(), &[]);
+            }
+        } else if let Some(motion) = event.try_cast::<InputEventMouseMotion>() {
+            let (count, index) = match self.dragging {
+                Some(v) => v,
+                None => return,
+            };
+            let peak = self.from_screen(motion.get_position());
+            if let Some(profile) = self.profile.as_mut() {
+                profile
+                    .bind_mut()
+                    .set_template_peak(self.phoneme, count, index, peak);
+            }
+            self.base.queue_redraw();
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use godot::engine::{
+    global::Error, FileAccess, IResourceFormatLoader, ResourceFormatLoader, ResourceLoader,
+};
+use godot::prelude::*;
+use std::cell::RefCell;
+
+use crate::{
+    analysis::{
+        error::LipSyncError,
+        model::{DataPoint, MouthShape, Phoneme, Profile},
+        profile,
+    },
+    convert::ErrorCode,
+};
+
+/// Extension of JSON profiles opened through `LipSyncProfileLoader`.
+const PROFILE_EXTENSION: &str = "lsprofile";
+const PROFILE_TYPE: &str = "LipSyncProfile";
+
+thread_local! {
+    static LOADER: RefCell<Option<Gd<LipSyncProfileLoader>>> = RefCell::new(None);
+}
+
+/// Phoneme templates and mouth shapes as a resource, savable as `.tres`/`.res`. Templates are
+/// stored as `(position, amplitude)` pairs, 3 then 4 per phoneme in `phonemes` order.
+#[derive(GodotClass)]
+#[class(base = Resource)]
+pub struct LipSyncProfile {
+    #[export]
+    phonemes: PackedStringArray,
+    #[export]
+    peaks3: PackedVector2Array,
+    #[export]
+    peaks4: PackedVector2Array,
+    /// `(open, form)` of every phoneme
+    #[export]
+    mouth_shapes: PackedVector2Array,
+    #[base]
+    base: Base<Resource>,
+}
+
+#[godot_api]
+impl LipSyncProfile {
+    /// Returns the profile in the JSON format read by `LipSyncProfileLoader`, or an empty
+    /// string if it is incomplete.
+    #[func]
+    pub fn to_json(&self) -> GString {
+        match self.to_profile() {
+            Ok(v) => GString::from(profile::to_json(&v)),
+            Err(e) => {
+                godot_error!("{}", e);
+                GString::new()
+            }
+        }
+    }
+
+    /// Reads a JSON profile. Returns null if it cannot be parsed.
+    #[func]
+    pub fn from_json(text: GString) -> Option<Gd<LipSyncProfile>> {
+        match profile::parse(&text.to_string()) {
+            Ok(v) => Some(LipSyncProfile::from_profile(&v)),
+            Err(e) => {
+                godot_error!("{}", e);
+                None
+            }
+        }
+    }
+
+    pub fn from_profile(profile: &Profile) -> Gd<Self> {
+        Gd::from_init_fn(|base| {
+            let mut out = LipSyncProfile::empty(base);
+            out.load_profile(profile);
+            out
+        })
+    }
+
+    pub fn to_profile(&self) -> Result<Profile, LipSyncError> {
+        let phonemes: Vec<String> = self
+            .phonemes
+            .as_slice()
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let to_phonemes = |peaks: &PackedVector2Array, count: usize| -> Vec<Phoneme> {
+            peaks
+                .as_slice()
+                .chunks(count)
+                .map(|v| Phoneme(v.iter().map(|p| DataPoint(p.x, p.y)).collect()))
+                .collect()
+        };
+
+        let profile = Profile {
+            peaks3: to_phonemes(&self.peaks3, 3),
+            peaks4: to_phonemes(&self.peaks4, 4),
+            mouth_shapes: self
+                .mouth_shapes
+                .as_slice()
+                .iter()
+                .map(|v| MouthShape::new(v.x, v.y))
+                .collect(),
+            phonemes,
+        };
+        profile::validate(&profile)?;
+
+        Ok(profile)
+    }
+
+    /// Template of `phoneme` for `count` peaks, empty if the profile has none.
+    pub fn template(&self, phoneme: usize, count: usize) -> Vec<DataPoint> {
+        let peaks = match count {
+            3 => &self.peaks3,
+            _ => &self.peaks4,
+        };
+        peaks
+            .as_slice()
+            .get(phoneme * count..(phoneme + 1) * count)
+            .map_or(vec![], |v| v.iter().map(|p| DataPoint(p.x, p.y)).collect())
+    }
+
+    /// Replaces one peak of a template, ignoring indices outside of the profile.
+    pub fn set_template_peak(
+        &mut self,
+        phoneme: usize,
+        count: usize,
+        index: usize,
+        peak: DataPoint,
+    ) {
+        let peaks = match count {
+            3 => &mut self.peaks3,
+            _ => &mut self.peaks4,
+        };
+        let i = phoneme * count + index;
+        if index < count && i < peaks.len() {
+            peaks.set(i, Vector2::new(peak.0, peak.1));
+            self.base.emit_changed();
+        }
+    }
+
+    fn empty(base: Base<Resource>) -> Self {
+        LipSyncProfile {
+            phonemes: PackedStringArray::new(),
+            peaks3: PackedVector2Array::new(),
+            peaks4: PackedVector2Array::new(),
+            mouth_shapes: PackedVector2Array::new(),
+            base,
+        }
+    }
+
+    fn load_profile(&mut self, profile: &Profile) {
+        let to_vectors = |peaks: &Vec<Phoneme>| -> Vec<Vector2> {
+            peaks
+                .iter()
+                .flat_map(|v| v.0.iter().map(|p| Vector2::new(p.0, p.1)))
+                .collect()
+        };
+        let mouth_shapes: Vec<Vector2> = profile
+            .mouth_shapes
+            .iter()
+            .map(|v| Vector2::new(v.open, v.form))
+            .collect();
+
+        self.phonemes = PackedStringArray::new();
+        for phoneme in profile.phonemes.iter() {
+            self.phonemes.push(GString::from(phoneme.as_str()));
+        }
+        self.peaks3 = PackedVector2Array::from(to_vectors(&profile.peaks3).as_slice());
+        self.peaks4 = PackedVector2Array::from(to_vectors(&profile.peaks4).as_slice());
+        self.mouth_shapes = PackedVector2Array::from(mouth_shapes.as_slice());
+    }
+}
+
+#[godot_api]
+impl IResource for LipSyncProfile {
+    /// New profiles start as a copy of the built-in one.
+    fn init(base: Base<Self::Base>) -> Self {
+        let mut out = LipSyncProfile::empty(base);
+        out.load_profile(&Profile::default());
+        out
+    }
+}
+
+/// Opens `.lsprofile` JSON profiles as `LipSyncProfile` resources.
+#[derive(GodotClass)]
+#[class(init, base = ResourceFormatLoader)]
+pub struct LipSyncProfileLoader {
+    #[base]
+    base: Base<ResourceFormatLoader>,
+}
+
+#[godot_api]
+impl IResourceFormatLoader for LipSyncProfileLoader {
+    fn get_recognized_extensions(&self) -> PackedStringArray {
+        let mut out = PackedStringArray::new();
+        out.push(GString::from(PROFILE_EXTENSION));
+        out
+    }
+
+    fn handles_type(&self, type_: StringName) -> bool {
+        type_ == StringName::from(PROFILE_TYPE) || type_ == StringName::from("Resource")
+    }
+
+    fn get_resource_type(&self, path: GString) -> GString {
+        if path
+            .to_string()
+            .ends_with(&format!(".{}", PROFILE_EXTENSION))
+        {
+            GString::from(PROFILE_TYPE)
+        } else {
+            GString::new()
+        }
+    }
+
+    fn load(
+        &self,
+        path: GString,
+        _original_path: GString,
+        _use_sub_threads: bool,
+        _cache_mode: i32,
+    ) -> Variant {
+        if !FileAccess::file_exists(path.clone()) {
+            return Variant::from(Error::ERR_FILE_NOT_FOUND);
+        }
+
+        let text = FileAccess::get_file_as_string(path.clone()).to_string();
+        match profile::parse(&text) {
+            Ok(v) => Variant::from(LipSyncProfile::from_profile(&v)),
+            Err(e) => {
+                godot_error!("Unable to load {}: {}", path, e);
+                Variant::from(e.code())
+            }
+        }
+    }
+}
+
+/// Registers the profile loader with the `ResourceLoader`.
+pub fn register_loader() {
+    let loader = Gd::<LipSyncProfileLoader>::new_default();
+    ResourceLoader::singleton().add_resource_format_loader(loader.clone().upcast());
+    LOADER.with(|v| *v.borrow_mut() = Some(loader));
+}
+
+pub fn unregister_loader() {
+    if let Some(loader) = LOADER.with(|v| v.borrow_mut().take()) {
+        ResourceLoader::singleton().remove_resource_format_loader(loader.upcast());
+    }
+}
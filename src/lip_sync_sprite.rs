@@ -0,0 +1,169 @@
+use godot::engine::{AnimatedSprite2D, Sprite2D, Texture2D};
+use godot::prelude::*;
+
+const LIP_SYNC_UPDATED: &str = "updated";
+const ON_UPDATED: &str = "on_lip_sync_updated";
+
+/// State used when the mouth should be closed, either because no vowel was detected
+/// or because the amount is below `open_threshold`.
+const SILENCE: i32 = -1;
+
+/// Swaps textures on a `Sprite2D` or frames on an `AnimatedSprite2D` based on the
+/// output of a `LipSyncRs` node.
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct LipSyncSprite {
+    /// Path to the `LipSyncRs` node to listen to.
+    #[export]
+    lip_sync_path: NodePath,
+    /// Path to the `Sprite2D` or `AnimatedSprite2D` to drive.
+    #[export]
+    target_path: NodePath,
+
+    #[export]
+    texture_silence: Option<Gd<Texture2D>>,
+    #[export]
+    texture_a: Option<Gd<Texture2D>>,
+    #[export]
+    texture_e: Option<Gd<Texture2D>>,
+    #[export]
+    texture_i: Option<Gd<Texture2D>>,
+    #[export]
+    texture_o: Option<Gd<Texture2D>>,
+    #[export]
+    texture_u: Option<Gd<Texture2D>>,
+
+    #[export]
+    frame_silence: i32,
+    #[export]
+    frame_a: i32,
+    #[export]
+    frame_e: i32,
+    #[export]
+    frame_i: i32,
+    #[export]
+    frame_o: i32,
+    #[export]
+    frame_u: i32,
+
+    /// Amounts below this are treated as silence.
+    #[export]
+    open_threshold: f32,
+    /// Number of updates a mouth shape must be held before it can change again.
+    #[export]
+    min_hold_frames: i32,
+
+    current: i32,
+    held_frames: i32,
+
+    #[base]
+    base: Base<Node>,
+}
+
+#[godot_api]
+impl LipSyncSprite {
+    #[func]
+    pub fn on_lip_sync_updated(&mut self, data: Dictionary) {
+        let vowel = data.get("vowel").map_or(SILENCE, |v| v.to::<i32>());
+        let amount = data.get("amount").map_or(0.0, |v| v.to::<f32>());
+
+        let desired = if vowel < 0 || amount < self.open_threshold {
+            SILENCE
+        } else {
+            vowel
+        };
+
+        self.held_frames += 1;
+        if desired == self.current || self.held_frames < self.min_hold_frames {
+            return;
+        }
+
+        self.current = desired;
+        self.held_frames = 0;
+        self.apply();
+    }
+
+    /// Returns the mouth state currently shown, -1 being silence.
+    #[func]
+    pub fn get_current_state(&self) -> i32 {
+        self.current
+    }
+
+    fn texture_for(&self, state: i32) -> Option<Gd<Texture2D>> {
+        match state {
+            0 => self.texture_a.clone(),
+            1 => self.texture_e.clone(),
+            2 => self.texture_i.clone(),
+            3 => self.texture_o.clone(),
+            4 => self.texture_u.clone(),
+            _ => self.texture_silence.clone(),
+        }
+    }
+
+    fn frame_for(&self, state: i32) -> i32 {
+        match state {
+            0 => self.frame_a,
+            1 => self.frame_e,
+            2 => self.frame_i,
+            3 => self.frame_o,
+            4 => self.frame_u,
+            _ => self.frame_silence,
+        }
+    }
+
+    fn apply(&mut self) {
+        let target = match self.base.get_node_or_null(self.target_path.clone()) {
+            Some(v) => v,
+            None => return,
+        };
+
+        if let Some(mut sprite) = target.clone().try_cast::<Sprite2D>() {
+            if let Some(texture) = self.texture_for(self.current) {
+                sprite.set_texture(texture);
+            }
+        } else if let Some(mut sprite) = target.try_cast::<AnimatedSprite2D>() {
+            sprite.set_frame(self.frame_for(self.current));
+        } else {
+            godot_print!("LipSyncSprite target must be a Sprite2D or AnimatedSprite2D");
+        }
+    }
+}
+
+#[godot_api]
+impl INode for LipSyncSprite {
+    fn init(base: Base<Self::Base>) -> Self {
+        LipSyncSprite {
+            lip_sync_path: NodePath::default(),
+            target_path: NodePath::default(),
+            texture_silence: None,
+            texture_a: None,
+            texture_e: None,
+            texture_i: None,
+            texture_o: None,
+            texture_u: None,
+            frame_silence: 0,
+            frame_a: 1,
+            frame_e: 2,
+            frame_i: 3,
+            frame_o: 4,
+            frame_u: 5,
+            open_threshold: 0.1,
+            min_hold_frames: 2,
+            current: SILENCE,
+            held_frames: 0,
+            base,
+        }
+    }
+
+    fn ready(&mut self) {
+        match self.base.get_node_or_null(self.lip_sync_path.clone()) {
+            Some(mut lip_sync) => {
+                let callable = Callable::from_object_method(self.base.clone(), ON_UPDATED);
+                lip_sync.connect(LIP_SYNC_UPDATED.into(), callable);
+            }
+            None => godot_print!("LipSyncSprite could not find LipSyncRs node"),
+        }
+
+        self.apply();
+    }
+}
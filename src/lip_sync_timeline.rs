@@ -0,0 +1,212 @@
+use godot::{engine::global::Error, prelude::*};
+
+use crate::{
+    analysis::{
+        formats::{papagayo, rhubarb},
+        model::MouthShape,
+        timeline::{Keyframe, Timeline},
+    },
+    convert::to_code,
+};
+
+/// A baked or imported lip sync timeline, savable as a resource. Keyframes are stored as
+/// parallel arrays so they serialize compactly.
+#[derive(GodotClass)]
+#[class(init, base = Resource)]
+pub struct LipSyncTimeline {
+    #[export]
+    phonemes: PackedStringArray,
+    #[export]
+    times: PackedFloat64Array,
+    #[export]
+    vowels: PackedInt32Array,
+    #[export]
+    amounts: PackedFloat32Array,
+    /// Per-phoneme weights of every keyframe, one after the other
+    #[export]
+    weights: PackedFloat32Array,
+    #[export]
+    mouth_open: PackedFloat32Array,
+    #[export]
+    mouth_form: PackedFloat32Array,
+    #[base]
+    base: Base<Resource>,
+}
+
+#[godot_api]
+impl LipSyncTimeline {
+    #[func]
+    pub fn get_keyframe_count(&self) -> i64 {
+        self.times.len() as i64
+    }
+
+    /// Time of the last keyframe, in seconds.
+    #[func]
+    pub fn get_length(&self) -> f64 {
+        self.to_timeline().length()
+    }
+
+    /// Returns every keyframe as a `Dictionary` with `time`, `vowel`, `amount`, `weights`,
+    /// `mouth_open` and `mouth_form`.
+    #[func]
+    pub fn get_keyframes(&self) -> Array<Dictionary> {
+        let mut out = Array::new();
+        for keyframe in self.to_timeline().keyframes.iter() {
+            out.push(Dictionary::from(keyframe));
+        }
+        out
+    }
+
+    /// Writes Rhubarb Lip Sync's TSV output. Keyframes quieter than `threshold` are written
+    /// as the idle X shape.
+    #[func]
+    pub fn to_rhubarb_tsv(&self, threshold: f32) -> GString {
+        GString::from(rhubarb::write_tsv(&self.to_timeline(), threshold))
+    }
+
+    #[func]
+    pub fn to_rhubarb_json(&self, threshold: f32, sound_file: GString) -> GString {
+        let json = rhubarb::write_json(&self.to_timeline(), threshold, &sound_file.to_string());
+        GString::from(json)
+    }
+
+    #[func]
+    pub fn to_rhubarb_xml(&self, threshold: f32, sound_file: GString) -> GString {
+        let xml = rhubarb::write_xml(&self.to_timeline(), threshold, &sound_file.to_string());
+        GString::from(xml)
+    }
+
+    /// Writes a Papagayo/Moho switch file using Preston Blair mouth shapes.
+    #[func]
+    pub fn to_papagayo(&self, threshold: f32, fps: f64) -> GString {
+        GString::from(papagayo::write(&self.to_timeline(), threshold, fps))
+    }
+
+    /// Reads Rhubarb Lip Sync's TSV, JSON or XML output. Returns null if it cannot be parsed,
+    /// use `load_rhubarb` to get the error code.
+    #[func]
+    pub fn from_rhubarb(text: GString) -> Option<Gd<LipSyncTimeline>> {
+        match rhubarb::read(&text.to_string()) {
+            Ok(v) => Some(LipSyncTimeline::from_timeline(&v)),
+            Err(e) => {
+                godot_error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Reads a Papagayo/Moho switch file. Returns null if it cannot be parsed, use
+    /// `load_papagayo` to get the error code.
+    #[func]
+    pub fn from_papagayo(text: GString, fps: f64) -> Option<Gd<LipSyncTimeline>> {
+        match papagayo::read(&text.to_string(), fps) {
+            Ok(v) => Some(LipSyncTimeline::from_timeline(&v)),
+            Err(e) => {
+                godot_error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Replaces the keyframes with Rhubarb Lip Sync's TSV, JSON or XML output. Returns
+    /// `ERR_INVALID_DATA` and keeps the current keyframes if it cannot be parsed.
+    #[func]
+    pub fn load_rhubarb(&mut self, text: GString) -> Error {
+        let result = rhubarb::read(&text.to_string()).map(|v| self.set_timeline(&v));
+        to_code(result)
+    }
+
+    /// Replaces the keyframes with a Papagayo/Moho switch file. Returns `ERR_INVALID_DATA` and
+    /// keeps the current keyframes if it cannot be parsed.
+    #[func]
+    pub fn load_papagayo(&mut self, text: GString, fps: f64) -> Error {
+        let result = papagayo::read(&text.to_string(), fps).map(|v| self.set_timeline(&v));
+        to_code(result)
+    }
+
+    pub fn from_timeline(timeline: &Timeline) -> Gd<Self> {
+        let mut out = Gd::from_init_fn(|base| LipSyncTimeline {
+            phonemes: PackedStringArray::new(),
+            times: PackedFloat64Array::new(),
+            vowels: PackedInt32Array::new(),
+            amounts: PackedFloat32Array::new(),
+            weights: PackedFloat32Array::new(),
+            mouth_open: PackedFloat32Array::new(),
+            mouth_form: PackedFloat32Array::new(),
+            base,
+        });
+        out.bind_mut().set_timeline(timeline);
+        out
+    }
+
+    pub fn set_timeline(&mut self, timeline: &Timeline) {
+        let mut phonemes = PackedStringArray::new();
+        for phoneme in timeline.phonemes.iter() {
+            phonemes.push(GString::from(phoneme.as_str()));
+        }
+
+        let keyframes = &timeline.keyframes;
+        let times: Vec<f64> = keyframes.iter().map(|v| v.time).collect();
+        let vowels: Vec<i32> = keyframes.iter().map(|v| v.vowel).collect();
+        let amounts: Vec<f32> = keyframes.iter().map(|v| v.amount).collect();
+        let weights: Vec<f32> = keyframes.iter().flat_map(|v| v.weights.clone()).collect();
+        let mouth_open: Vec<f32> = keyframes.iter().map(|v| v.mouth.open).collect();
+        let mouth_form: Vec<f32> = keyframes.iter().map(|v| v.mouth.form).collect();
+
+        self.phonemes = phonemes;
+        self.times = PackedFloat64Array::from(times.as_slice());
+        self.vowels = PackedInt32Array::from(vowels.as_slice());
+        self.amounts = PackedFloat32Array::from(amounts.as_slice());
+        self.weights = PackedFloat32Array::from(weights.as_slice());
+        self.mouth_open = PackedFloat32Array::from(mouth_open.as_slice());
+        self.mouth_form = PackedFloat32Array::from(mouth_form.as_slice());
+    }
+
+    pub fn to_timeline(&self) -> Timeline {
+        let phonemes: Vec<String> = self
+            .phonemes
+            .as_slice()
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let stride = phonemes.len();
+        let weights = self.weights.as_slice();
+
+        let mut timeline = Timeline::new(phonemes);
+        for (i, time) in self.times.as_slice().iter().enumerate() {
+            let keyframe_weights = if weights.len() >= (i + 1) * stride {
+                weights[i * stride..(i + 1) * stride].to_vec()
+            } else {
+                vec![0.0; stride]
+            };
+
+            timeline.keyframes.push(Keyframe {
+                time: *time,
+                vowel: self.vowels.as_slice().get(i).copied().unwrap_or(-1),
+                amount: self.amounts.as_slice().get(i).copied().unwrap_or(0.0),
+                weights: keyframe_weights,
+                mouth: MouthShape::new(
+                    self.mouth_open.as_slice().get(i).copied().unwrap_or(0.0),
+                    self.mouth_form.as_slice().get(i).copied().unwrap_or(0.0),
+                ),
+            });
+        }
+
+        timeline
+    }
+}
+
+impl From<&Keyframe> for Dictionary {
+    fn from(k: &Keyframe) -> Self {
+        let mut dict = Dictionary::new();
+
+        dict.insert("time", k.time);
+        dict.insert("vowel", k.vowel);
+        dict.insert("amount", k.amount);
+        dict.insert("weights", PackedFloat32Array::from(k.weights.as_slice()));
+        dict.insert("mouth_open", k.mouth.open);
+        dict.insert("mouth_form", k.mouth.form);
+
+        dict
+    }
+}
@@ -0,0 +1,126 @@
+use godot::engine::{AudioServer, AudioStreamPlayer};
+use godot::prelude::*;
+
+use crate::{
+    analysis::{
+        model::{MouthShape, VowelEstimate},
+        timeline::{Keyframe, Timeline},
+    },
+    lip_sync_timeline::LipSyncTimeline,
+};
+
+const LIP_SYNC_UPDATED: &str = "updated";
+
+/// Plays a `LipSyncTimeline` back in sync with an `AudioStreamPlayer`, emitting the same
+/// `updated` dictionaries as `LipSyncRs` so sprites and other listeners can be reused.
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct LipSyncTimelinePlayer {
+    #[export]
+    #[var(get, set = set_timeline)]
+    timeline: Option<Gd<LipSyncTimeline>>,
+    /// Path to the `AudioStreamPlayer` whose playback position drives the timeline.
+    #[export]
+    audio_player_path: NodePath,
+    /// Seconds added to the playback position, to compensate for mouths lagging the audio.
+    #[export]
+    offset: f64,
+
+    cached: Timeline,
+    last_time: Option<f64>,
+
+    #[base]
+    base: Base<Node>,
+}
+
+#[godot_api]
+impl LipSyncTimelinePlayer {
+    #[signal]
+    fn updated(data: Dictionary);
+
+    #[func]
+    pub fn set_timeline(&mut self, timeline: Option<Gd<LipSyncTimeline>>) {
+        self.cached = timeline
+            .as_ref()
+            .map_or_else(Timeline::default, |v| v.bind().to_timeline());
+        self.timeline = timeline;
+        self.last_time = None;
+    }
+
+    /// Returns the timeline's state at `time` seconds, in the shape of `updated`.
+    #[func]
+    pub fn sample(&self, time: f64) -> Dictionary {
+        self.cached.sample(time).map_or_else(Dictionary::new, |v| {
+            Dictionary::from(VowelEstimate::from(&v))
+        })
+    }
+
+    /// Returns the audio time currently heard, or `None` while the player is stopped or
+    /// paused. The position is only updated once per mix, so the time since the last mix is
+    /// added, scaled by the pitch, and the output latency removed.
+    fn playback_time(&self) -> Option<f64> {
+        let player = self
+            .base
+            .get_node_or_null(self.audio_player_path.clone())?
+            .try_cast::<AudioStreamPlayer>()?;
+        if !player.is_playing() || player.get_stream_paused() {
+            return None;
+        }
+
+        let server = AudioServer::singleton();
+        let since_mix = server.get_time_since_last_mix() * player.get_pitch_scale() as f64;
+        let time = player.get_playback_position() as f64 + since_mix - server.get_output_latency();
+
+        Some((time + self.offset).max(0.0))
+    }
+
+    fn emit(&mut self, keyframe: Keyframe) {
+        let data = Dictionary::from(VowelEstimate::from(&keyframe));
+        self.base
+            .emit_signal(LIP_SYNC_UPDATED.into(), &[Variant::from(data)]);
+    }
+}
+
+#[godot_api]
+impl INode for LipSyncTimelinePlayer {
+    fn init(base: Base<Self::Base>) -> Self {
+        LipSyncTimelinePlayer {
+            timeline: None,
+            audio_player_path: NodePath::default(),
+            offset: 0.0,
+            cached: Timeline::default(),
+            last_time: None,
+            base,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        match self.playback_time() {
+            Some(time) => {
+                // Seeking moves the playback position, so sampling it every frame follows
+                // jumps in either direction. Nothing is emitted while it stands still.
+                if self.last_time == Some(time) {
+                    return;
+                }
+                self.last_time = Some(time);
+
+                if let Some(keyframe) = self.cached.sample(time) {
+                    self.emit(keyframe);
+                }
+            }
+            None => {
+                // Close the mouth once when playback stops or pauses
+                if let Some(time) = self.last_time.take() {
+                    let silence = Keyframe {
+                        time,
+                        vowel: -1,
+                        amount: 0.0,
+                        weights: vec![0.0; self.cached.phonemes.len()],
+                        mouth: MouthShape::zero(),
+                    };
+                    self.emit(silence);
+                }
+            }
+        }
+    }
+}